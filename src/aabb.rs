@@ -1,10 +1,6 @@
 use crate::ray::*;
 use crate::vec3::*;
 use std::cmp::Ordering;
-use std::mem;
-
-#[cfg(target_arch = "x86_64")]
-use core::arch::x86_64::*;
 
 type T = f32;
 
@@ -18,8 +14,8 @@ impl BoundingBox {
   pub fn new(minimum: Point, maximum: Point) -> BoundingBox {
     BoundingBox { minimum, maximum }
   }
-  pub fn hit(&self, mut t_min: T, mut t_max: T, ray: &Ray) -> bool {
-    return self.hit_vectorized(t_min, t_max, ray);
+  pub fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> bool {
+    self.hit_vectorized(t_min, t_max, ray)
     /*for a in 0..3 {
       let inv_d = 1.0 / ray.direction[a];
       let mut t0 = (self.minimum[a] - ray.origin[a]) * inv_d;
@@ -46,7 +42,42 @@ impl BoundingBox {
     t_min = t_min.max(tsmaller.max_element());
     t_max = t_max.min(tbigger.min_element());
 
-    return t_min < t_max;
+    t_min < t_max
+  }
+
+  // The smallest box around the points, if there are any.
+  pub fn around<I: IntoIterator<Item = Point>>(
+    points: I,
+  ) -> Option<BoundingBox> {
+    points.into_iter().fold(None, |bb, p| {
+      Some(match bb {
+        None => BoundingBox::new(p, p),
+        Some(bb) => BoundingBox::new(
+          Point(bb.minimum.0.min(p.0)),
+          Point(bb.maximum.0.max(p.0)),
+        ),
+      })
+    })
+  }
+
+  pub fn padded(&self, padding: T) -> BoundingBox {
+    let padding = Vec3::new(padding, padding, padding);
+    BoundingBox {
+      minimum: self.minimum - padding,
+      maximum: self.maximum + padding,
+    }
+  }
+
+  pub fn corners(&self) -> [Point; 8] {
+    let (lo, hi) = (self.minimum.0, self.maximum.0);
+    let corner = |i: usize| {
+      Point::new(
+        if i & 1 == 0 { lo.x() } else { hi.x() },
+        if i & 2 == 0 { lo.y() } else { hi.y() },
+        if i & 4 == 0 { lo.z() } else { hi.z() },
+      )
+    };
+    [0, 1, 2, 3, 4, 5, 6, 7].map(corner)
   }
 
   pub fn surrounding_box(bb1: &BoundingBox, bb2: &BoundingBox) -> BoundingBox {
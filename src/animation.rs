@@ -0,0 +1,273 @@
+// Keyframed animation: values that change over time by interpolating
+// between keys, for the camera and for objects, and the frames an animation
+// is rendered as. Times are in seconds.
+use crate::vec3::*;
+
+type T = f32;
+
+// How a track moves from one key to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+  // At a constant rate, turning sharply at each key.
+  Linear,
+  // Along a cubic Bezier curve, which passes smoothly through the keys on
+  // either side. Its handles come from the neighboring keys, as for a
+  // Catmull-Rom spline, and are flat at the ends of the track, so motion
+  // eases in and out.
+  Bezier,
+}
+
+// Values that can be interpolated: anything with weighted sums.
+pub trait Animatable: Copy {
+  // The sum of the values, each times its weight.
+  fn weighted_sum(terms: &[(T, Self)]) -> Self;
+
+  // The value s of the way from a to b.
+  fn lerp(a: Self, b: Self, s: T) -> Self {
+    Self::weighted_sum(&[(1.0 - s, a), (s, b)])
+  }
+}
+
+impl Animatable for T {
+  fn weighted_sum(terms: &[(T, T)]) -> T {
+    terms.iter().map(|&(w, x)| w * x).sum()
+  }
+}
+
+impl Animatable for Vec3 {
+  fn weighted_sum(terms: &[(T, Vec3)]) -> Vec3 {
+    let mut sum = Vec3::new(0.0, 0.0, 0.0);
+    for &(w, v) in terms {
+      sum += v * w;
+    }
+    sum
+  }
+}
+
+impl Animatable for Point {
+  fn weighted_sum(terms: &[(T, Point)]) -> Point {
+    let mut sum = Vec3::new(0.0, 0.0, 0.0);
+    for &(w, p) in terms {
+      sum += p.0 * w;
+    }
+    Point(sum)
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Key<V> {
+  time: T,
+  value: V,
+  // How the track gets from this key to the next.
+  interpolation: Interpolation,
+}
+
+// A value over time. Before its first key and after its last it holds
+// still.
+#[derive(Clone, Debug)]
+pub struct Track<V> {
+  keys: Vec<Key<V>>,
+}
+
+impl<V: Animatable> Track<V> {
+  // A track with a single key, to add more to.
+  pub fn new(time: T, value: V, interpolation: Interpolation) -> Track<V> {
+    Track {
+      keys: vec![Key {
+        time,
+        value,
+        interpolation,
+      }],
+    }
+  }
+
+  pub fn constant(value: V) -> Track<V> {
+    Track::new(0.0, value, Interpolation::Linear)
+  }
+
+  // Adds a key, replacing any already at time.
+  pub fn with_key(
+    mut self,
+    time: T,
+    value: V,
+    interpolation: Interpolation,
+  ) -> Track<V> {
+    let key = Key {
+      time,
+      value,
+      interpolation,
+    };
+    let i = self.keys.partition_point(|k| k.time < time);
+    if self.keys.get(i).is_some_and(|k| k.time == time) {
+      self.keys[i] = key;
+    } else {
+      self.keys.insert(i, key);
+    }
+    self
+  }
+
+  pub fn at(&self, time: T) -> V {
+    let last = self.keys.len() - 1;
+    let i = self.keys.partition_point(|k| k.time <= time);
+    if i == 0 {
+      return self.keys[0].value;
+    }
+    if i > last {
+      return self.keys[last].value;
+    }
+    let (a, b) = (&self.keys[i - 1], &self.keys[i]);
+    let s = (time - a.time) / (b.time - a.time);
+    match a.interpolation {
+      Interpolation::Linear => V::lerp(a.value, b.value, s),
+      Interpolation::Bezier => {
+        // De Casteljau's construction, so values with their own lerp, like
+        // rotations, follow their own kind of curve.
+        let [p0, p1, p2, p3] = self.control_points(i - 1);
+        let (q0, q1, q2) =
+          (V::lerp(p0, p1, s), V::lerp(p1, p2, s), V::lerp(p2, p3, s));
+        V::lerp(V::lerp(q0, q1, s), V::lerp(q1, q2, s), s)
+      }
+    }
+  }
+
+  // The Bezier control points from key i to the next.
+  fn control_points(&self, i: usize) -> [V; 4] {
+    let keys = &self.keys;
+    let duration = keys[i + 1].time - keys[i].time;
+    // The handle at key j, a third of the way along the segment, with the
+    // slope of the line between its neighbors.
+    let handle = |j: usize, sign: T| {
+      if j == 0 || j == keys.len() - 1 {
+        return keys[j].value;
+      }
+      let (before, after) = (&keys[j - 1], &keys[j + 1]);
+      let w = sign * duration / (3.0 * (after.time - before.time));
+      V::weighted_sum(&[
+        (1.0, keys[j].value),
+        (w, after.value),
+        (-w, before.value),
+      ])
+    };
+    [
+      keys[i].value,
+      handle(i, 1.0),
+      handle(i + 1, -1.0),
+      keys[i + 1].value,
+    ]
+  }
+
+  // Whether the track holds still from time0 to time1, being before its
+  // first key or after its last all that time.
+  pub fn holds_still(&self, time0: T, time1: T) -> bool {
+    time1 <= self.keys[0].time || time0 >= self.keys[self.keys.len() - 1].time
+  }
+
+  // Values whose convex hull holds every value the track takes from time0
+  // to time1, for bounding moving objects.
+  pub fn hull(&self, time0: T, time1: T) -> Vec<V> {
+    let mut points = vec![self.at(time0), self.at(time1)];
+    for i in 0..self.keys.len() {
+      let key = &self.keys[i];
+      if key.time > time0 && key.time < time1 {
+        points.push(key.value);
+      }
+      // Bezier curves stay within their control points.
+      let overlaps = i + 1 < self.keys.len()
+        && key.time < time1
+        && self.keys[i + 1].time > time0;
+      if overlaps && key.interpolation == Interpolation::Bezier {
+        points.extend_from_slice(&self.control_points(i));
+      }
+    }
+    points
+  }
+}
+
+// The camera's keyframed state: where it is, what it looks at, its vertical
+// field of view in degrees and its focus distance.
+#[derive(Clone, Debug)]
+pub struct CameraRig {
+  pub position: Track<Point>,
+  pub look_at: Track<Point>,
+  pub vfov: Track<T>,
+  pub focus_distance: Track<T>,
+}
+
+impl CameraRig {
+  // Whether the camera holds still from time0 to time1, so it needn't be
+  // moved while the shutter is open.
+  pub fn holds_still(&self, time0: T, time1: T) -> bool {
+    self.position.holds_still(time0, time1)
+      && self.look_at.holds_still(time0, time1)
+      && self.vfov.holds_still(time0, time1)
+      && self.focus_distance.holds_still(time0, time1)
+  }
+}
+
+// Which frames of an animation to render, and how long the shutter is open
+// for each.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameRange {
+  pub first: u32,
+  pub last: u32,
+  pub fps: T,
+  // The fraction of each frame that the shutter is open for, in degrees of
+  // a rotary shutter: 360 is the whole frame, 180 half of it.
+  pub shutter_angle: T,
+  // When in each frame the shutter opens, also in degrees. -90 centers a
+  // 180 degree shutter on the frame's time.
+  pub shutter_offset: T,
+}
+
+impl FrameRange {
+  // When the shutter opens and closes for a frame.
+  pub fn shutter(&self, frame: u32) -> (T, T) {
+    let open = (frame as T + self.shutter_offset / 360.0) / self.fps;
+    (open, open + self.shutter_angle / 360.0 / self.fps)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_interpolation() {
+    let linear = Track::new(2.0, 4.0, Interpolation::Linear)
+      .with_key(0.0, 1.0, Interpolation::Linear)
+      .with_key(0.0, 2.0, Interpolation::Linear);
+    assert_eq!(linear.at(-1.0), 2.0);
+    assert_eq!(linear.at(1.5), 3.5);
+    assert_eq!(linear.at(3.0), 4.0);
+
+    // Bezier eases out of the first key and in to the last, passing
+    // through the middle one.
+    let bezier = Track::new(0.0, 0.0, Interpolation::Bezier)
+      .with_key(1.0, 1.0, Interpolation::Bezier)
+      .with_key(2.0, 2.0, Interpolation::Bezier);
+    assert!(bezier.at(0.1) < 0.1);
+    assert!((bezier.at(1.0) - 1.0).abs() < 1e-6);
+    // Without a kink there.
+    assert!((bezier.at(0.9) + bezier.at(1.1) - 2.0).abs() < 1e-5);
+    for &x in &bezier.hull(0.0, 2.0) {
+      assert!((0.0..=2.0).contains(&x));
+    }
+    assert!(bezier.holds_still(-1.0, 0.0) && bezier.holds_still(2.0, 3.0));
+    assert!(!bezier.holds_still(1.5, 2.5));
+  }
+
+  #[test]
+  fn test_frame_shutter() {
+    let frames = FrameRange {
+      first: 0,
+      last: 9,
+      fps: 24.0,
+      shutter_angle: 180.0,
+      shutter_offset: -90.0,
+    };
+    // Centered on the frame at 0.5 seconds, open for half of it.
+    let (open, close) = frames.shutter(12);
+    assert!((open + close - 1.0).abs() < 1e-6);
+    assert!((close - open - 1.0 / 48.0).abs() < 1e-6);
+  }
+}
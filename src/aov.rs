@@ -0,0 +1,244 @@
+// Arbitrary output variables: images of what camera rays hit first, such as
+// its depth, normal or albedo, rendered alongside the beauty image for
+// compositing. Like the beauty image, each pixel averages its samples, so
+// values blend at edges, except for IDs.
+use crate::camera::*;
+use crate::material2::*;
+use crate::object::*;
+use crate::ray::*;
+use crate::vec3::*;
+use std::collections::HashMap;
+
+type T = f32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+  // The hit point, in world space.
+  Position,
+  // The distance from the camera to the hit point, in every channel.
+  Depth,
+  // The shading normal, facing the camera.
+  Normal,
+  // The surface's color, without lighting.
+  Albedo,
+  // A color for each object or material, which identifies it. A pixel
+  // takes the color of its first sample, since a blend of two would
+  // identify neither.
+  ObjectId,
+  MaterialId,
+  // The surface coordinates, in red and green.
+  Uv,
+  // How far the hit point moves across the image while the shutter is
+  // open, in pixels right and up, in red and green.
+  Motion,
+}
+
+pub const ALL_AOVS: [Aov; 8] = [
+  Aov::Position,
+  Aov::Depth,
+  Aov::Normal,
+  Aov::Albedo,
+  Aov::ObjectId,
+  Aov::MaterialId,
+  Aov::Uv,
+  Aov::Motion,
+];
+
+impl Aov {
+  // The AOV's name on the command line, and in its images' names.
+  pub fn name(&self) -> &'static str {
+    match self {
+      Aov::Position => "position",
+      Aov::Depth => "depth",
+      Aov::Normal => "normal",
+      Aov::Albedo => "albedo",
+      Aov::ObjectId => "object-id",
+      Aov::MaterialId => "material-id",
+      Aov::Uv => "uv",
+      Aov::Motion => "motion",
+    }
+  }
+
+  // Whether the AOV holds measurements rather than colors: values that can
+  // be negative or far above 1, which only float images keep.
+  pub fn is_data(&self) -> bool {
+    matches!(
+      self,
+      Aov::Position | Aov::Depth | Aov::Normal | Aov::Uv | Aov::Motion
+    )
+  }
+
+  // Whether a pixel averages the AOV's values over its samples.
+  pub fn blends(&self) -> bool {
+    !matches!(self, Aov::ObjectId | Aov::MaterialId)
+  }
+
+  // The extension of the AOV's images, given that of the beauty image's.
+  // Data is always saved as .pfm, which keeps its values exact. It stands in
+  // for OpenEXR, which compositors usually take but the image crate can't
+  // write.
+  pub fn format(&self, format: &'static str) -> &'static str {
+    if self.is_data() {
+      "pfm"
+    } else {
+      format
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Aov> {
+    ALL_AOVS.iter().copied().find(|aov| aov.name() == name)
+  }
+}
+
+fn address<X: ?Sized>(x: &X) -> usize {
+  x as *const X as *const () as usize
+}
+
+// Indices of the world's objects and materials, for ID passes. Both count
+// from 1 in the order objects were added to the world.
+#[derive(Default)]
+pub struct SceneIds {
+  objects: HashMap<usize, u32>,
+  materials: HashMap<usize, u32>,
+}
+
+impl SceneIds {
+  // Adds an object with the given index, which objects added together,
+  // like the triangles of a mesh, share.
+  pub fn add(&mut self, object: &dyn Object, index: u32) {
+    self.objects.insert(address(object), index);
+    if let Some(material) = object.material() {
+      let next = self.materials.len() as u32 + 1;
+      self.materials.entry(address(material)).or_insert(next);
+    }
+  }
+
+  fn object(&self, object: &dyn Object) -> u32 {
+    self.objects.get(&address(object)).copied().unwrap_or(0)
+  }
+
+  fn material(&self, material: &Material) -> u32 {
+    self.materials.get(&address(material)).copied().unwrap_or(0)
+  }
+}
+
+// A bright color for an index, far from its neighbors' so that adjacent
+// objects stand apart. 0 is black.
+fn id_color(id: u32) -> Color {
+  if id == 0 {
+    return Color::new(0.0, 0.0, 0.0);
+  }
+  // A few rounds of an integer hash.
+  let mut h = id.wrapping_mul(0x9e37_79b9);
+  h ^= h >> 16;
+  h = h.wrapping_mul(0x85eb_ca6b);
+  h ^= h >> 13;
+  let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as T / 255.0;
+  Color::new(channel(0), channel(8), channel(16))
+}
+
+// Records the AOVs of camera rays' first hits, for one camera.
+pub struct AovRecorder<'a> {
+  aovs: &'a [Aov],
+  ids: &'a SceneIds,
+  camera: &'a Camera,
+  // The image's size in pixels, for motion vectors.
+  width: T,
+  height: T,
+}
+
+impl AovRecorder<'_> {
+  pub fn new<'a>(
+    aovs: &'a [Aov],
+    ids: &'a SceneIds,
+    camera: &'a Camera,
+    (width, height): (u32, u32),
+  ) -> AovRecorder<'a> {
+    AovRecorder {
+      aovs,
+      ids,
+      camera,
+      width: width as T,
+      height: height as T,
+    }
+  }
+
+  // Sets each AOV's value at a hit in values, which has one for each.
+  pub fn record(
+    &self,
+    hit: &HitResult,
+    payload: &HitResultPayload,
+    ray: &Ray,
+    values: &mut [Color],
+  ) {
+    let material = payload.material;
+    for (aov, value) in self.aovs.iter().zip(values.iter_mut()) {
+      *value = match aov {
+        Aov::Position => Color(payload.p.0),
+        Aov::Depth => {
+          let depth = (payload.p - ray.origin).norm();
+          Color::new(depth, depth, depth)
+        }
+        Aov::Normal => Color(material.shading_normal(ray, payload)),
+        Aov::Albedo => material.albedo(payload),
+        Aov::ObjectId => id_color(self.ids.object(hit.obj)),
+        Aov::MaterialId => id_color(self.ids.material(material)),
+        Aov::Uv => Color::new(payload.u, payload.v, 0.0),
+        Aov::Motion => self.motion(hit, ray),
+      };
+    }
+  }
+
+  // Adds a sample's values, as set by record, to its pixel's sums. IDs
+  // don't blend, so their sums keep the first sample's instead.
+  pub fn accumulate(&self, sums: &mut [Color], sample: &[Color], first: bool) {
+    for ((aov, sum), &value) in self.aovs.iter().zip(sums).zip(sample) {
+      if aov.blends() {
+        *sum += value;
+      } else if first {
+        *sum = value;
+      }
+    }
+  }
+
+  fn motion(&self, hit: &HitResult, ray: &Ray) -> Color {
+    let (open, close) = self.camera.shutter_interval();
+    let image_point = |time| {
+      let p = hit.obj.position_at(hit.t, ray, time);
+      self.camera.image_point(p, time)
+    };
+    match (image_point(open), image_point(close)) {
+      (Some((s0, t0)), Some((s1, t1))) => {
+        Color::new((s1 - s0) * self.width, (t1 - t0) * self.height, 0.0)
+      }
+      _ => Color::new(0.0, 0.0, 0.0),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::texture::*;
+
+  #[test]
+  fn test_ids() {
+    let material =
+      std::sync::Arc::new(Material::new_lambertian(Texture::constant(0.5)));
+    let sphere =
+      |x| Sphere::new(Point::new(x, 0.0, 0.0), 1.0, material.clone());
+    let (a, b, c) = (sphere(0.0), sphere(3.0), sphere(6.0));
+    let mut ids = SceneIds::default();
+    ids.add(&a, 1);
+    ids.add(&b, 2);
+    // Objects that weren't added have no index, but their material has.
+    assert_eq!(ids.object(&b), 2);
+    assert_eq!(ids.object(&c), 0);
+    assert_eq!(ids.material(a.material().unwrap()), 1);
+    assert_eq!(ids.material(c.material().unwrap()), 1);
+    assert!(ALL_AOVS
+      .iter()
+      .all(|aov| Aov::from_name(aov.name()) == Some(*aov)));
+    assert_eq!(id_color(0).r() + id_color(0).g() + id_color(0).b(), 0.0);
+  }
+}
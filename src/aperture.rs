@@ -0,0 +1,147 @@
+// The shape of the lens's opening, which is the shape out of focus
+// highlights take.
+extern crate image;
+use image::DynamicImage;
+use rand::Rng;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+type T = f32;
+
+#[derive(Clone, Debug)]
+pub enum Aperture {
+  Disk,
+  // A regular polygon with this many blades, its corners on the unit circle,
+  // turned counterclockwise by rotation radians.
+  Polygon { blades: u32, rotation: T },
+  // An image of the opening, where brighter pixels let more light through.
+  Image(Arc<ApertureImage>),
+}
+
+impl Aperture {
+  // A uniformly distributed point of the opening, which fits in the unit
+  // disk.
+  pub fn sample(&self) -> (T, T) {
+    let mut rng = rand::thread_rng();
+    match self {
+      Aperture::Disk => {
+        let r = rng.gen::<T>().sqrt();
+        let theta = 2.0 * PI * rng.gen::<T>();
+        (r * theta.cos(), r * theta.sin())
+      }
+      Aperture::Polygon { blades, rotation } => {
+        // The polygon is a fan of equal triangles around its center. Pick
+        // one, then a point in it.
+        let blades = (*blades).max(3);
+        let i = rng.gen_range(0..blades);
+        let corner = |i: u32| {
+          let angle = rotation + 2.0 * PI * i as T / blades as T;
+          (angle.cos(), angle.sin())
+        };
+        let (a, b) = (corner(i), corner(i + 1));
+        let (mut s, mut t): (T, T) = (rng.gen(), rng.gen());
+        if s + t > 1.0 {
+          s = 1.0 - s;
+          t = 1.0 - t;
+        }
+        (s * a.0 + t * b.0, s * a.1 + t * b.1)
+      }
+      Aperture::Image(image) => image.sample(&mut rng),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct ApertureImage {
+  width: u32,
+  height: u32,
+  // The running total of the pixels' brightness, in row-major order, ending
+  // at 1.
+  cdf: Vec<T>,
+}
+
+impl ApertureImage {
+  pub fn new(image: &DynamicImage) -> Result<ApertureImage, String> {
+    let image = image.to_luma8();
+    let mut total = 0.0;
+    let mut cdf: Vec<T> = image
+      .pixels()
+      .map(|p| {
+        total += p[0] as T;
+        total
+      })
+      .collect();
+    if total <= 0.0 {
+      return Err("The aperture image is black".to_string());
+    }
+    for c in cdf.iter_mut() {
+      *c /= total;
+    }
+    Ok(ApertureImage {
+      width: image.width(),
+      height: image.height(),
+      cdf,
+    })
+  }
+
+  // A point of the image, chosen in proportion to its brightness. The image
+  // is centered on the origin, with its corners on the unit circle.
+  fn sample<R: Rng>(&self, rng: &mut R) -> (T, T) {
+    let u: T = rng.gen();
+    let i = self
+      .cdf
+      .partition_point(|&c| c <= u)
+      .min(self.cdf.len() - 1);
+    let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+    let diagonal = (self.width as T).hypot(self.height as T);
+    let x = (x as T + rng.gen::<T>()) - 0.5 * self.width as T;
+    // Image rows go down, lens coordinates go up.
+    let y = 0.5 * self.height as T - (y as T + rng.gen::<T>());
+    (2.0 * x / diagonal, 2.0 * y / diagonal)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use image::{GrayImage, Luma};
+
+  #[test]
+  fn test_disk_and_polygon_fill_their_shape() {
+    let n = 10000;
+    let mut r2 = 0.0;
+    for _ in 0..n {
+      let (x, y) = Aperture::Disk.sample();
+      assert!(x * x + y * y <= 1.0);
+      r2 += x * x + y * y;
+    }
+    // Uniform over the disk, not on its boundary.
+    assert!((r2 / n as T - 0.5).abs() < 0.02);
+
+    // A square with its corners on the axes.
+    let square = Aperture::Polygon {
+      blades: 4,
+      rotation: 0.0,
+    };
+    for _ in 0..n {
+      let (x, y) = square.sample();
+      assert!(x.abs() + y.abs() <= 1.0 + 1e-5);
+    }
+  }
+
+  #[test]
+  fn test_image_aperture_samples_bright_pixels() {
+    // Only the top right pixel of four is open.
+    let image = GrayImage::from_fn(2, 2, |x, y| {
+      Luma([if (x, y) == (1, 0) { 255 } else { 0 }])
+    });
+    let aperture = ApertureImage::new(&DynamicImage::ImageLuma8(image));
+    let aperture = Aperture::Image(Arc::new(aperture.unwrap()));
+    for _ in 0..100 {
+      let (x, y) = aperture.sample();
+      assert!(x >= 0.0 && y >= 0.0 && x * x + y * y <= 1.0 + 1e-5);
+    }
+    let black = GrayImage::new(2, 2);
+    assert!(ApertureImage::new(&DynamicImage::ImageLuma8(black)).is_err());
+  }
+}
@@ -0,0 +1,149 @@
+// Images and materials shared across a scene. Each image file is read and
+// decoded once however many textures use it, and materials registered by
+// name are shared between the objects that use them.
+extern crate image;
+use crate::image_texture::*;
+use crate::material2::*;
+use crate::texture::*;
+use image::codecs::hdr::HdrDecoder;
+use image::{DynamicImage, Rgb};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+enum DecodedImage {
+  Integer(DynamicImage),
+  Float {
+    width: u32,
+    height: u32,
+    pixels: Vec<Rgb<f32>>,
+  },
+}
+
+fn decode(path: &str) -> Result<DecodedImage, String> {
+  let is_hdr = Path::new(path)
+    .extension()
+    .is_some_and(|e| e.eq_ignore_ascii_case("hdr"));
+  let error = |e: &dyn std::fmt::Display| format!("{}: {}", path, e);
+  if !is_hdr {
+    return image::open(path)
+      .map(DecodedImage::Integer)
+      .map_err(|e| error(&e));
+  }
+  let file = File::open(path).map_err(|e| error(&e))?;
+  let decoder = HdrDecoder::new(BufReader::new(file)).map_err(|e| error(&e))?;
+  let metadata = decoder.metadata();
+  let pixels = decoder.read_image_hdr().map_err(|e| error(&e))?;
+  Ok(DecodedImage::Float {
+    width: metadata.width,
+    height: metadata.height,
+    pixels,
+  })
+}
+
+pub struct Assets {
+  filter: Filter,
+  wrap: WrapMode,
+  images: HashMap<String, Arc<DecodedImage>>,
+  // Textures built from the images, which differ by how they're decoded.
+  textures: HashMap<(String, ColorSpace), Arc<ImageTexture>>,
+  materials: HashMap<String, Arc<Material>>,
+}
+
+impl Assets {
+  // Image textures will use the given filter and wrap mode.
+  pub fn new(filter: Filter, wrap: WrapMode) -> Assets {
+    Assets {
+      filter,
+      wrap,
+      images: HashMap::new(),
+      textures: HashMap::new(),
+      materials: HashMap::new(),
+    }
+  }
+
+  // An image texture. color_space says how to decode 8 and 16-bit images:
+  // Srgb for color maps, Linear for data maps. Radiance .hdr files are
+  // always linear.
+  pub fn image(
+    &mut self,
+    path: &str,
+    color_space: ColorSpace,
+  ) -> Result<Texture, String> {
+    let key = (path.to_string(), color_space);
+    if let Some(texture) = self.textures.get(&key) {
+      return Ok(Texture::Image(texture.clone()));
+    }
+    let image = match self.images.get(path) {
+      Some(image) => image.clone(),
+      None => {
+        let image = Arc::new(decode(path)?);
+        self.images.insert(path.to_string(), image.clone());
+        image
+      }
+    };
+    let texture = Arc::new(match *image {
+      DecodedImage::Integer(ref image) => {
+        ImageTexture::new(image, color_space, self.filter, self.wrap)
+      }
+      DecodedImage::Float {
+        width,
+        height,
+        ref pixels,
+      } => ImageTexture::from_hdr(width, height, pixels, self.filter, self.wrap),
+    });
+    self.textures.insert(key, texture.clone());
+    Ok(Texture::Image(texture))
+  }
+
+  // The material registered under name, making and registering it first if
+  // there is none.
+  pub fn material<F: FnOnce() -> Material>(
+    &mut self,
+    name: &str,
+    make: F,
+  ) -> Arc<Material> {
+    self
+      .materials
+      .entry(name.to_string())
+      .or_insert_with(|| Arc::new(make()))
+      .clone()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_images_are_loaded_once() {
+    let path = std::env::temp_dir().join(format!(
+      "raytracer_{}_test_images_are_loaded_once.png",
+      std::process::id()
+    ));
+    let path = path.to_str().unwrap();
+    image::RgbImage::from_pixel(2, 2, Rgb([255, 0, 0]))
+      .save(path)
+      .unwrap();
+    let mut assets = Assets::new(Filter::Nearest, WrapMode::Repeat);
+    let same = |a: &Texture, b: &Texture| match (a, b) {
+      (Texture::Image(a), Texture::Image(b)) => Arc::ptr_eq(a, b),
+      _ => false,
+    };
+    let a = assets.image(path, ColorSpace::Srgb).unwrap();
+    let b = assets.image(path, ColorSpace::Srgb).unwrap();
+    let c = assets.image(path, ColorSpace::Linear).unwrap();
+    assert!(same(&a, &b) && !same(&a, &c));
+    assert_eq!(assets.images.len(), 1);
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_errors_name_the_path() {
+    let mut assets = Assets::new(Filter::Nearest, WrapMode::Repeat);
+    let e = assets.image("no/such/image.png", ColorSpace::Srgb);
+    assert!(e.err().unwrap().contains("no/such/image.png"));
+  }
+}
@@ -94,7 +94,7 @@ impl BVHNode {
 }
 
 impl Object for BVHNode {
-  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult> {
+  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult<'_>> {
     if !self.bounding_box.hit(t_min, t_max, ray) {
       return None;
     }
@@ -118,7 +118,7 @@ impl Object for BVHNode {
       }
     }
   }
-  fn hit_payload(&self, _t: T, _ray: &Ray) -> HitResultPayload {
+  fn hit_payload(&self, _t: T, _ray: &Ray) -> HitResultPayload<'_> {
     panic!("A BVH should never be asked to provide a hit payload.");
   }
   fn bounding_box(&self, _time0: T, _time1: T) -> Option<BoundingBox> {
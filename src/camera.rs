@@ -1,11 +1,135 @@
+use crate::animation::*;
+use crate::aperture::*;
+use crate::lens_system::*;
 use crate::ray::*;
 use crate::vec3::*;
 use rand::Rng;
 use std::f32::consts::PI;
+use std::sync::Arc;
 type T = f32;
 
-#[derive(Copy, Clone)]
-pub struct Camera {
+// How the camera maps image positions to directions. Only the perspective
+// and orthographic projections have a lens; the others are pinholes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CameraProjection {
+  // A thin lens, with vfov as the vertical field of view.
+  Perspective,
+  // Parallel rays, framing the same rectangle of the focus plane that the
+  // perspective projection would.
+  Orthographic,
+  // An equidistant fisheye, where the angle from the view direction grows
+  // linearly with the distance from the image's center. vfov is the angle
+  // across the image's height, and parts of the image beyond 180 degrees
+  // from the view direction are left black.
+  Fisheye,
+  // The whole sphere of directions, longitude across and latitude up, as for
+  // VR panoramas. Images should be twice as wide as they are tall.
+  Equirectangular,
+}
+
+// How far open the shutter is while it's open, which weights when rays are
+// traced.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShutterCurve {
+  // Fully open at once, for the whole interval.
+  Box,
+  // Opening and closing at a steady rate, each over this fraction of the
+  // interval. A half makes a triangle.
+  Trapezoid { ramp: T },
+}
+
+impl ShutterCurve {
+  // How far through the interval, from 0 to 1, a ray is traced at, given u
+  // uniform in [0, 1).
+  fn sample(self, u: T) -> T {
+    match self {
+      ShutterCurve::Box => u,
+      ShutterCurve::Trapezoid { ramp } => {
+        let ramp = ramp.clamp(1e-4, 0.5);
+        // Invert the area under the curve, which opens over [0, ramp] and
+        // closes over [1 - ramp, 1].
+        let area = u * (1.0 - ramp);
+        if area < 0.5 * ramp {
+          (2.0 * ramp * area).sqrt()
+        } else if area <= 1.0 - 1.5 * ramp {
+          area + 0.5 * ramp
+        } else {
+          1.0 - (2.0 * ramp * (1.0 - ramp - area)).max(0.0).sqrt()
+        }
+      }
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Eye {
+  Left,
+  Right,
+}
+
+// How the two eyes of a stereo render are laid out.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+  // Left eye on the left, in one image twice as wide.
+  SideBySide,
+  // Left eye on top, in one image twice as tall.
+  TopBottom,
+  // Each eye in its own image.
+  Separate,
+}
+
+// A photographic camera's settings, from which the size of its lens and the
+// brightness of its image follow. Radiance is taken to be in cd/m^2, and
+// scene units in metres.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Exposure {
+  pub f_number: T,
+  // In seconds.
+  pub shutter: T,
+  pub iso: T,
+}
+
+// The height of a 35mm film frame, in metres.
+const SENSOR_HEIGHT: T = 0.024;
+
+impl Default for Exposure {
+  // Settings for a dim scene, like ours with its sky of 1 cd/m^2.
+  fn default() -> Exposure {
+    Exposure {
+      f_number: 1.4,
+      shutter: 0.25,
+      iso: 800.0,
+    }
+  }
+}
+
+impl Exposure {
+  // The focal length that gives a vertical field of view of vfov degrees.
+  pub fn focal_length(&self, vfov: T) -> T {
+    0.5 * SENSOR_HEIGHT / (0.5 * vfov.to_radians()).tan()
+  }
+
+  // The diameter of the lens's opening.
+  pub fn aperture(&self, vfov: T) -> T {
+    self.focal_length(vfov) / self.f_number
+  }
+
+  // The exposure value these settings would be at ISO 100.
+  pub fn ev100(&self) -> T {
+    (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2()
+  }
+
+  // What radiance is multiplied by to get the image's values, so that the
+  // saturation based ISO speed has 1 be the brightest value recorded.
+  pub fn scale(&self) -> T {
+    1.0 / (1.2 * self.ev100().exp2())
+  }
+}
+
+// Where the camera is, where it looks and how far it sees, which a rig
+// changes while the shutter is open.
+#[derive(Clone, Copy)]
+struct Pose {
   origin: Point,
   lower_left_corner: Point,
   horizontal: Vec3,
@@ -16,31 +140,23 @@ pub struct Camera {
   //   * u, v form the basis of the plane tangential to the lens.
   u: Vec3,
   v: Vec3,
-  _w: Vec3,
-  lens_radius: T,
-  time0: T,
-  time1: T
-}
-
-// S^1 here is embedded into R^3 by the mapping (x, y) -> (x, y, 0).
-fn random_vector_in_s1() -> (T, T) {
-  let mut rng = rand::thread_rng();
-  let theta = rng.gen::<T>() * 2.0 * PI;
-  (theta.cos(), theta.sin())
+  w: Vec3,
+  focus_distance: T,
+  vfov: T,
+  // The height of the viewport at unit distance from the lens.
+  viewport_height: T,
 }
 
-impl Camera {
-  pub fn new(
+impl Pose {
+  // Places and aims the camera, with vfov in degrees.
+  fn new(
     lookfrom: Point,
     lookat: Point,
     vup: Vec3,
     vfov: T,
-    aspect_ratio: T,
-    aperture: T,
     focus_distance: T,
-    time0: T,
-    time1: T
-  ) -> Camera {
+    aspect_ratio: T,
+  ) -> Pose {
     let theta = vfov.to_radians();
     let h = (theta / 2.0).tan();
 
@@ -56,30 +172,450 @@ impl Camera {
     let vertical = focus_distance * viewport_height * v;
     let towards_camera = focus_distance * w;
 
+    Pose {
+      origin,
+      lower_left_corner: origin
+        - towards_camera
+        - horizontal / 2.0
+        - vertical / 2.0,
+      horizontal,
+      vertical,
+      u,
+      v,
+      w,
+      focus_distance,
+      vfov: theta,
+      viewport_height,
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct Camera {
+  projection: CameraProjection,
+  // The camera's pose when the shutter opens.
+  pose: Pose,
+  vup: Vec3,
+  // Moves the camera while the shutter is open, when set.
+  rig: Option<Arc<CameraRig>>,
+  lens_radius: T,
+  aperture: Aperture,
+  // Replaces the projection and thin lens when set.
+  lens_system: Option<Arc<LensSystem>>,
+  // How far this eye is to the right of the rig's center, for stereo.
+  eye_offset: T,
+  aspect_ratio: T,
+  time0: T,
+  time1: T,
+  shutter_curve: ShutterCurve,
+  // The width of a single pixel's footprint at the camera, and the angle it
+  // covers. See set_image_height.
+  pixel_width: T,
+  pixel_spread: T,
+}
+
+impl Camera {
+  #[allow(clippy::too_many_arguments)]
+  pub fn new(
+    lookfrom: Point,
+    lookat: Point,
+    vup: Vec3,
+    vfov: T,
+    aspect_ratio: T,
+    aperture: T,
+    focus_distance: T,
+    time0: T,
+    time1: T
+  ) -> Camera {
     Camera {
-      origin: origin,
-      lower_left_corner: origin - towards_camera - horizontal / 2.0 - vertical / 2.0,
-      horizontal: horizontal,
-      vertical: vertical,
-      u: u,
-      v: v,
-      _w: w,
+      projection: CameraProjection::Perspective,
+      pose: Pose::new(
+        lookfrom,
+        lookat,
+        vup,
+        vfov,
+        focus_distance,
+        aspect_ratio,
+      ),
+      vup,
+      rig: None,
       lens_radius: aperture / 2.0,
-      time0: time0,
-      time1: time1
+      aperture: Aperture::Disk,
+      lens_system: None,
+      eye_offset: 0.0,
+      aspect_ratio,
+      time0,
+      time1,
+      shutter_curve: ShutterCurve::Box,
+      pixel_width: 0.0,
+      pixel_spread: 0.0,
+    }
+  }
+
+  // Moves the camera along the rig's tracks while the shutter is open, so
+  // its motion blurs the image. The aperture and pixel footprints stay as
+  // they were set for the shutter's opening.
+  pub fn with_rig(mut self, rig: Arc<CameraRig>) -> Camera {
+    self.rig = Some(rig);
+    self
+  }
+
+  // The camera's pose at the given time.
+  fn pose_at(&self, time: T) -> Pose {
+    match &self.rig {
+      None => self.pose,
+      Some(rig) => Pose::new(
+        rig.position.at(time),
+        rig.look_at.at(time),
+        self.vup,
+        rig.vfov.at(time),
+        rig.focus_distance.at(time),
+        self.aspect_ratio,
+      ),
     }
   }
 
-  pub fn get_ray(&self, s: T, t: T) -> Ray {
+  pub fn with_projection(mut self, projection: CameraProjection) -> Camera {
+    self.projection = projection;
+    self
+  }
+
+  // The lens's opening takes the given shape, scaled to fit in the circle
+  // of the aperture's diameter.
+  pub fn with_aperture(mut self, aperture: Aperture) -> Camera {
+    self.aperture = aperture;
+    self
+  }
+
+  pub fn with_shutter_curve(mut self, shutter_curve: ShutterCurve) -> Camera {
+    self.shutter_curve = shutter_curve;
+    self
+  }
+
+  // Traces rays through a real lens instead of projecting them. The lens
+  // should be focused at this camera's focus distance.
+  pub fn with_lens_system(mut self, lens_system: Arc<LensSystem>) -> Camera {
+    self.lens_system = Some(lens_system);
+    self
+  }
+
+  // This camera as one eye of a stereo rig, with the eyes interpupillary
+  // distance apart.
+  //
+  // Eyes of planar projections move sideways but keep aiming at the same
+  // points of the focus plane, so objects there have no parallax. Panoramic
+  // eyes circle the rig's center, always to the side of the ray, as in
+  // omni-directional stereo, so the whole panorama is stereo.
+  pub fn for_eye(mut self, eye: Eye, interpupillary_distance: T) -> Camera {
+    let half = 0.5 * interpupillary_distance;
+    self.eye_offset = match eye {
+      Eye::Left => -half,
+      Eye::Right => half,
+    };
+    self
+  }
+
+  // When the shutter opens and closes.
+  pub fn shutter_interval(&self) -> (T, T) {
+    (self.time0, self.time1)
+  }
+
+  // Tells the camera how many pixels tall the image is, so the rays it
+  // generates know how large a footprint each one covers, for texture
+  // filtering. Until this is called rays have no footprint.
+  pub fn set_image_height(&mut self, image_height: u32) {
+    let pixels = image_height as T;
+    let (width, spread) = match (&self.lens_system, self.projection) {
+      (Some(lens), _) => (0.0, lens.vfov()),
+      (None, CameraProjection::Perspective) => (0.0, self.pose.viewport_height),
+      (None, CameraProjection::Orthographic) => {
+        (self.pose.focus_distance * self.pose.viewport_height, 0.0)
+      }
+      (None, CameraProjection::Fisheye) => (0.0, self.pose.vfov),
+      (None, CameraProjection::Equirectangular) => (0.0, PI),
+    };
+    self.pixel_width = width / pixels;
+    self.pixel_spread = spread / pixels;
+  }
+
+  // The ray through (s, t), each from 0 to 1 across the image, or None if
+  // the projection doesn't cover that point.
+  pub fn get_ray(&self, s: T, t: T) -> Option<Ray> {
     let mut rng = rand::thread_rng();
-    let (vx, vy) = random_vector_in_s1();
-    let offset = self.lens_radius * (self.u * vx + self.v * vy);
-    Ray {
-      origin: self.origin + offset,
-      direction: self.lower_left_corner + s * self.horizontal + t * self.vertical
-        - self.origin
-        - offset,
-      time: rng.gen_range(self.time0 .. self.time1)
+    let time = self.time0
+      + (self.time1 - self.time0) * self.shutter_curve.sample(rng.gen());
+    let pose = self.pose_at(time);
+    let (origin, direction) = match &self.lens_system {
+      Some(lens) => {
+        // Lens space looks along +z, with the front of the lens at the
+        // camera's origin.
+        let (o, d) = lens.sample_ray(s, t)?;
+        let to_world =
+          |v: Vec3| v.x() * pose.u + v.y() * pose.v - v.z() * pose.w;
+        (
+          pose.origin + self.eye_offset * pose.u + to_world(o),
+          to_world(d),
+        )
+      }
+      None => self.project(&pose, s, t)?,
+    };
+    Some(Ray {
+      origin,
+      direction,
+      time,
+      wavelength: None,
+      cone: RayCone {
+        width: self.pixel_width,
+        spread: self.pixel_spread,
+      },
+      media: Media::none(),
+    })
+  }
+
+  // The ray's origin and direction by the projection and thin lens.
+  fn project(&self, pose: &Pose, s: T, t: T) -> Option<(Point, Vec3)> {
+    let (vx, vy) = self.aperture.sample();
+    let offset =
+      self.lens_radius * (pose.u * vx + pose.v * vy) + self.eye_offset * pose.u;
+    Some(match self.projection {
+      CameraProjection::Perspective => (
+        pose.origin + offset,
+        pose.lower_left_corner + s * pose.horizontal + t * pose.vertical
+          - pose.origin
+          - offset,
+      ),
+      CameraProjection::Orthographic => {
+        // Parallel rays through the same points of the focus plane as the
+        // perspective projection, defocused the same way.
+        let on_lens =
+          pose.origin + (s - 0.5) * pose.horizontal + (t - 0.5) * pose.vertical;
+        let focus = on_lens - pose.focus_distance * pose.w;
+        (on_lens + offset, focus - (on_lens + offset))
+      }
+      CameraProjection::Fisheye => {
+        let x = (2.0 * s - 1.0) * self.aspect_ratio;
+        let y = 2.0 * t - 1.0;
+        let r = (x * x + y * y).sqrt();
+        let theta = 0.5 * r * pose.vfov;
+        if theta > PI {
+          return None;
+        }
+        let (cos_phi, sin_phi) =
+          if r > 0.0 { (x / r, y / r) } else { (1.0, 0.0) };
+        let sideways = theta.sin() * (cos_phi * pose.u + sin_phi * pose.v);
+        (
+          pose.origin + self.eye_offset * pose.u,
+          sideways - theta.cos() * pose.w,
+        )
+      }
+      CameraProjection::Equirectangular => {
+        let longitude = (s - 0.5) * 2.0 * PI;
+        let latitude = (t - 0.5) * PI;
+        let around = longitude.sin() * pose.u - longitude.cos() * pose.w;
+        let right = longitude.cos() * pose.u + longitude.sin() * pose.w;
+        (
+          pose.origin + self.eye_offset * right,
+          latitude.cos() * around + latitude.sin() * pose.v,
+        )
+      }
+    })
+  }
+
+  // Where a point appears in the image at the given time, as the (s, t)
+  // that get_ray takes, or None if it's behind the camera. Lens systems
+  // aren't inverted.
+  pub fn image_point(&self, p: Point, time: T) -> Option<(T, T)> {
+    self.posed_image_point(&self.pose_at(time), p)
+  }
+
+  fn posed_image_point(&self, pose: &Pose, p: Point) -> Option<(T, T)> {
+    if self.lens_system.is_some() {
+      return None;
+    }
+    let center = pose.origin + self.eye_offset * pose.u;
+    let d = p - center;
+    let (x, y, z) = (d.dot(pose.u), d.dot(pose.v), -d.dot(pose.w));
+    match self.projection {
+      CameraProjection::Perspective => {
+        if z <= 0.0 {
+          return None;
+        }
+        // Through the focus plane, where the image's corners are.
+        let q = center + d * (pose.focus_distance / z) - pose.lower_left_corner;
+        Some((
+          q.dot(pose.horizontal) / pose.horizontal.norm_squared(),
+          q.dot(pose.vertical) / pose.vertical.norm_squared(),
+        ))
+      }
+      CameraProjection::Orthographic => Some((
+        d.dot(pose.horizontal) / pose.horizontal.norm_squared() + 0.5,
+        d.dot(pose.vertical) / pose.vertical.norm_squared() + 0.5,
+      )),
+      CameraProjection::Fisheye => {
+        let r = (x * x + y * y).sqrt();
+        let theta = r.atan2(z);
+        let scale = if r > 0.0 {
+          2.0 * theta / pose.vfov / r
+        } else {
+          0.0
+        };
+        Some((
+          0.5 * (x * scale / self.aspect_ratio + 1.0),
+          0.5 * (y * scale + 1.0),
+        ))
+      }
+      CameraProjection::Equirectangular => {
+        let longitude = x.atan2(z);
+        let latitude = (y / d.norm()).asin();
+        Some((longitude / (2.0 * PI) + 0.5, latitude / PI + 0.5))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn camera(projection: CameraProjection) -> Camera {
+    Camera::new(
+      Point::new(0.0, 0.0, 0.0),
+      Point::new(0.0, 0.0, -1.0),
+      Vec3::new(0.0, 1.0, 0.0),
+      180.0,
+      2.0,
+      0.0,
+      1.0,
+      0.0,
+      1.0,
+    )
+    .with_projection(projection)
+  }
+
+  fn direction(projection: CameraProjection, s: T, t: T) -> Vec3 {
+    camera(projection).get_ray(s, t).unwrap().direction.normalize()
+  }
+
+  #[test]
+  fn test_panoramic_projections() {
+    let close = |a: Vec3, b: Vec3| (a - b).norm() < 1e-5;
+    let forward = Vec3::new(0.0, 0.0, -1.0);
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    for &projection in &[
+      CameraProjection::Fisheye,
+      CameraProjection::Equirectangular,
+    ] {
+      assert!(close(direction(projection, 0.5, 0.5), forward));
+      assert!(close(direction(projection, 0.5, 1.0), up));
+    }
+    // The equirectangular image wraps around behind the camera.
+    let back = direction(CameraProjection::Equirectangular, 0.0, 0.5);
+    assert!(close(back, Vec3::new(0.0, 0.0, 1.0)));
+    // A 180 degree fisheye doesn't reach the corners of a wide image.
+    assert!(camera(CameraProjection::Fisheye).get_ray(0.0, 0.0).is_none());
+  }
+
+  #[test]
+  fn test_stereo_eyes() {
+    let left = camera(CameraProjection::Perspective).for_eye(Eye::Left, 0.1);
+    let right = camera(CameraProjection::Perspective).for_eye(Eye::Right, 0.1);
+    let l = left.get_ray(0.3, 0.6).unwrap();
+    let r = right.get_ray(0.3, 0.6).unwrap();
+    assert!((r.origin - l.origin - Vec3::new(0.1, 0.0, 0.0)).norm() < 1e-6);
+    // Both eyes see the same point at the focus distance.
+    assert!((l.at(1.0) - r.at(1.0)).norm() < 1e-5);
+
+    // Looking backwards, the panoramic left eye is on the other side.
+    let ods = camera(CameraProjection::Equirectangular).for_eye(Eye::Left, 0.1);
+    let forward = ods.get_ray(0.5, 0.5).unwrap().origin;
+    let backward = ods.get_ray(0.0, 0.5).unwrap().origin;
+    assert!((forward - Point::new(-0.05, 0.0, 0.0)).norm() < 1e-6);
+    assert!((backward - Point::new(0.05, 0.0, 0.0)).norm() < 1e-6);
+  }
+
+  #[test]
+  fn test_shutter_curves() {
+    let triangle = ShutterCurve::Trapezoid { ramp: 0.5 };
+    assert!((triangle.sample(0.5) - 0.5).abs() < 1e-6);
+    // A triangle puts an eighth of its rays in the first quarter.
+    assert!((triangle.sample(0.125) - 0.25).abs() < 1e-6);
+    let trapezoid = ShutterCurve::Trapezoid { ramp: 0.2 };
+    let mut last = 0.0;
+    for i in 0..=100 {
+      let x = trapezoid.sample(i as T / 100.0);
+      assert!(x >= last && x <= 1.0);
+      last = x;
+    }
+    assert!((last - 1.0).abs() < 1e-4);
+  }
+
+  #[test]
+  fn test_exposure() {
+    let exposure = Exposure {
+      f_number: 2.0,
+      shutter: 0.25,
+      iso: 100.0,
+    };
+    assert!((exposure.ev100() - 4.0).abs() < 1e-5);
+    // Twice the ISO is one stop brighter.
+    let faster = Exposure {
+      iso: 200.0,
+      ..exposure
+    };
+    assert!((faster.scale() / exposure.scale() - 2.0).abs() < 1e-4);
+    // A 50mm lens at f/2 is 25mm across.
+    let vfov = 2.0 * (0.012 as T / 0.05).atan().to_degrees();
+    assert!((exposure.aperture(vfov) - 0.025).abs() < 1e-5);
+  }
+
+  #[test]
+  fn test_orthographic_rays_are_parallel() {
+    let camera = camera(CameraProjection::Orthographic);
+    let a = camera.get_ray(0.1, 0.2).unwrap();
+    let b = camera.get_ray(0.9, 0.7).unwrap();
+    assert!((a.direction.normalize() - b.direction.normalize()).norm() < 1e-5);
+    assert!((a.origin - b.origin).norm() > 0.1);
+  }
+
+  #[test]
+  fn test_image_point_inverts_get_ray() {
+    for &projection in &[
+      CameraProjection::Perspective,
+      CameraProjection::Orthographic,
+      CameraProjection::Fisheye,
+      CameraProjection::Equirectangular,
+    ] {
+      let camera = camera(projection);
+      let p = camera.get_ray(0.3, 0.6).unwrap().at(2.0);
+      let (s, t) = camera.image_point(p, 0.0).unwrap();
+      assert!((s - 0.3).abs() < 1e-5 && (t - 0.6).abs() < 1e-5);
+    }
+  }
+
+  #[test]
+  fn test_rig_moves_camera_while_shutter_is_open() {
+    let track = |from: Point, to: Point| {
+      Track::new(0.0, from, Interpolation::Linear).with_key(
+        1.0,
+        to,
+        Interpolation::Linear,
+      )
+    };
+    let rig = CameraRig {
+      position: track(Point::new(0.0, 0.0, 0.0), Point::new(1.0, 0.0, 0.0)),
+      look_at: track(Point::new(0.0, 0.0, -1.0), Point::new(1.0, 0.0, -1.0)),
+      vfov: Track::constant(90.0),
+      focus_distance: Track::constant(1.0),
+    };
+    let camera = camera(CameraProjection::Perspective).with_rig(Arc::new(rig));
+    for _ in 0..16 {
+      let ray = camera.get_ray(0.5, 0.5).unwrap();
+      assert!((ray.origin.0.x() - ray.time).abs() < 1e-5);
     }
+    // A point that stays put moves left in the image as the camera passes.
+    let p = Point::new(0.5, 0.0, -1.0);
+    let (s0, _) = camera.image_point(p, 0.0).unwrap();
+    let (s1, _) = camera.image_point(p, 1.0).unwrap();
+    assert!(s0 > 0.5 && s1 < 0.5);
   }
 }
@@ -1,9 +1,19 @@
 extern crate image;
 use crate::vec3::*;
-use image::{ImageBuffer, RgbImage};
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::{ImageBuffer, Rgb, RgbImage};
+use png::{BitDepth, ColorType, Encoder};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
 
+// The film: linear colors, kept as they were rendered until the image is
+// saved.
 pub struct Canvas {
-  img: RgbImage,
+  width: u32,
+  height: u32,
+  // In rows from the top, as image files have them.
+  pixels: Vec<Color>,
 }
 
 type T = f32;
@@ -15,25 +25,254 @@ fn clamp(x: T, lo: T, hi: T) -> T {
   if x > hi {
     return hi;
   }
-  return x;
+  x
+}
+
+fn has_extension(name: &str, extension: &str) -> bool {
+  Path::new(name)
+    .extension()
+    .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+fn is_hdr(name: &str) -> bool {
+  has_extension(name, "hdr")
+}
+
+// A rectangle of pixels, from (x0, y0) up to but not including (x1, y1),
+// with y going up from the bottom row, as for Canvas::draw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelRect {
+  pub x0: u32,
+  pub y0: u32,
+  pub x1: u32,
+  pub y1: u32,
+}
+
+impl PixelRect {
+  pub fn width(&self) -> u32 {
+    self.x1 - self.x0
+  }
+
+  pub fn height(&self) -> u32 {
+    self.y1 - self.y0
+  }
+}
+
+// The part of an image to render. Both kinds are given as x0, x1, y0, y1
+// with y going down from the top of the image, as in image editors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CropWindow {
+  // Fractions of the image's width and height, from 0 to 1.
+  Normalized([T; 4]),
+  // Pixels, with the ends excluded.
+  Pixels([u32; 4]),
+}
+
+impl CropWindow {
+  // The window's pixels in an image of the given size. Normalized windows
+  // cover every pixel they overlap.
+  pub fn rect(&self, width: u32, height: u32) -> Result<PixelRect, String> {
+    let [x0, x1, y0, y1] = match *self {
+      CropWindow::Normalized([x0, x1, y0, y1]) => {
+        let (w, h) = (width as T, height as T);
+        [
+          (x0 * w).floor() as u32,
+          (x1 * w).ceil() as u32,
+          (y0 * h).floor() as u32,
+          (y1 * h).ceil() as u32,
+        ]
+      }
+      CropWindow::Pixels(bounds) => bounds,
+    };
+    let (x1, y1) = (x1.min(width), y1.min(height));
+    if x0 >= x1 || y0 >= y1 {
+      return Err(format!(
+        "The crop window is outside the {}x{} image",
+        width, height
+      ));
+    }
+    Ok(PixelRect {
+      x0,
+      y0: height - y1,
+      x1,
+      y1: height - y0,
+    })
+  }
 }
 
 impl Canvas {
   pub fn new(width: u32, height: u32) -> Canvas {
     Canvas {
-      img: ImageBuffer::new(width, height),
+      width,
+      height,
+      pixels: vec![Color::new(0.0, 0.0, 0.0); (width * height) as usize],
+    }
+  }
+
+  // Reads a previously saved image back in, to render more of it. Radiance
+  // .hdr files come back exactly, others as closely as their 8 bits allow.
+  pub fn load(name: &str) -> Result<Canvas, String> {
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", name, e);
+    if is_hdr(name) {
+      let file = File::open(name).map_err(|e| error(&e))?;
+      let decoder =
+        HdrDecoder::new(BufReader::new(file)).map_err(|e| error(&e))?;
+      let metadata = decoder.metadata();
+      let pixels = decoder.read_image_hdr().map_err(|e| error(&e))?;
+      return Ok(Canvas {
+        width: metadata.width,
+        height: metadata.height,
+        pixels: pixels
+          .iter()
+          .map(|p| Color::new(p[0], p[1], p[2]))
+          .collect(),
+      });
+    }
+    if has_extension(name, "pfm") {
+      let file = File::open(name).map_err(|e| error(&e))?;
+      return Canvas::read_pfm(BufReader::new(file)).map_err(|e| error(&e));
     }
+    let img = image::open(name).map_err(|e| error(&e))?.to_rgb8();
+    Ok(Canvas {
+      width: img.width(),
+      height: img.height(),
+      pixels: img.pixels().map(Self::from_rgb).collect(),
+    })
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  // The color at a pixel, with y going up as for draw.
+  pub fn get(&self, x: u32, y: u32) -> Color {
+    self.pixels[((self.height - 1 - y) * self.width + x) as usize]
   }
 
   pub fn draw(&mut self, x: u32, y: u32, c: &Color) {
     // We flip the y coordinate because conceptually our origin is the bottom-left corner.
-    self
-      .img
-      .put_pixel(x, self.img.height() - 1 - y, Self::to_rgb(c))
+    let row = self.height - 1 - y;
+    self.pixels[(row * self.width + x) as usize] = *c;
   }
 
-  pub fn save(&self, name: &str) {
-    self.img.save(name).unwrap();
+  // Saves as a Radiance .hdr file if the name ends in .hdr, a .pfm file of
+  // 32 bit floats if it ends in .pfm, and as an 8 bit image otherwise. Only
+  // .pfm files keep negative values, which Radiance files can't hold. PNG
+  // files keep the metadata, as tEXt chunks of keywords and text.
+  pub fn save(
+    &self,
+    name: &str,
+    metadata: &[(String, String)],
+  ) -> Result<(), String> {
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", name, e);
+    if is_hdr(name) {
+      let file = File::create(name).map_err(|e| error(&e))?;
+      let pixels: Vec<_> = self
+        .pixels
+        .iter()
+        .map(|c| Rgb([c.r(), c.g(), c.b()]))
+        .collect();
+      return HdrEncoder::new(BufWriter::new(file))
+        .encode(&pixels, self.width as usize, self.height as usize)
+        .map_err(|e| error(&e));
+    }
+    if has_extension(name, "pfm") {
+      let file = File::create(name).map_err(|e| error(&e))?;
+      return self.write_pfm(BufWriter::new(file)).map_err(|e| error(&e));
+    }
+    let img: RgbImage =
+      ImageBuffer::from_fn(self.width, self.height, |x, y| {
+        Self::to_rgb(&self.pixels[(y * self.width + x) as usize])
+      });
+    if !has_extension(name, "png") {
+      return img.save(name).map_err(|e| error(&e));
+    }
+    let file = File::create(name).map_err(|e| error(&e))?;
+    let mut encoder =
+      Encoder::new(BufWriter::new(file), self.width, self.height);
+    encoder.set_color(ColorType::RGB);
+    encoder.set_depth(BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+    for (keyword, text) in metadata {
+      let chunk = [keyword.as_bytes(), &[0], text.as_bytes()].concat();
+      writer
+        .write_chunk(*b"tEXt", &chunk)
+        .map_err(|e| error(&e))?;
+    }
+    writer
+      .write_image_data(&img.into_raw())
+      .map_err(|e| error(&e))
+  }
+
+  // Portable float maps are a text header of "PF", the size and a scale
+  // whose sign gives the byte order, then the pixels' channels as floats,
+  // in rows from the bottom.
+  fn write_pfm<W: Write>(&self, mut w: W) -> std::io::Result<()> {
+    write!(w, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+    for row in self.pixels.chunks(self.width as usize).rev() {
+      for c in row {
+        for x in &[c.r(), c.g(), c.b()] {
+          w.write_all(&x.to_le_bytes())?;
+        }
+      }
+    }
+    w.flush()
+  }
+
+  fn read_pfm<R: BufRead>(mut r: R) -> Result<Canvas, String> {
+    let mut header = Vec::new();
+    // The header's three lines: "PF", the size and the scale.
+    for _ in 0..3 {
+      let mut line = String::new();
+      r.read_line(&mut line).map_err(|e| e.to_string())?;
+      header.push(line.trim().to_string());
+    }
+    let bad = || "Bad PFM header".to_string();
+    if header[0] != "PF" {
+      return Err(bad());
+    }
+    let size: Vec<u32> = header[1]
+      .split_whitespace()
+      .map(|x| x.parse().map_err(|_| bad()))
+      .collect::<Result<_, _>>()?;
+    let (width, height) = match size[..] {
+      [width, height] => (width, height),
+      _ => return Err(bad()),
+    };
+    let scale: T = header[2].parse().map_err(|_| bad())?;
+    // Three 4 byte floats per pixel, which a bad header could overflow.
+    let bytes = (width as usize)
+      .checked_mul(height as usize)
+      .and_then(|pixels| pixels.checked_mul(12))
+      .ok_or_else(bad)?;
+    let mut data = vec![0; bytes];
+    r.read_exact(&mut data).map_err(|e| e.to_string())?;
+    let floats: Vec<T> = data
+      .chunks(4)
+      .map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if scale < 0.0 {
+          T::from_le_bytes(b)
+        } else {
+          T::from_be_bytes(b)
+        }
+      })
+      .collect();
+    // Back to rows from the top.
+    let pixels = floats
+      .chunks(3 * width as usize)
+      .rev()
+      .flat_map(|row| row.chunks(3).map(|c| Color::new(c[0], c[1], c[2])))
+      .collect();
+    Ok(Canvas {
+      width,
+      height,
+      pixels,
+    })
   }
 
   fn to_rgb(c: &Color) -> image::Rgb<u8> {
@@ -42,4 +281,57 @@ impl Canvas {
     let b = 256.0 * clamp(c.b().sqrt(), 0.0, 0.999);
     image::Rgb([r.floor() as u8, g.floor() as u8, b.floor() as u8])
   }
+
+  // The color at the middle of the range that to_rgb maps to p.
+  fn from_rgb(p: &image::Rgb<u8>) -> Color {
+    let linear = |x: u8| ((x as T + 0.5) / 256.0).powi(2);
+    Color::new(linear(p[0]), linear(p[1]), linear(p[2]))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_crop_window_rect() {
+    // The top left quarter, which is the upper half of the rows.
+    let quarter = CropWindow::Normalized([0.0, 0.5, 0.0, 0.5]);
+    let expected = PixelRect {
+      x0: 0,
+      y0: 45,
+      x1: 80,
+      y1: 90,
+    };
+    assert_eq!(quarter.rect(160, 90), Ok(expected));
+    let pixels = CropWindow::Pixels([10, 20, 0, 5]).rect(160, 90).unwrap();
+    assert_eq!((pixels.width(), pixels.height(), pixels.y1), (10, 5, 90));
+    assert!(CropWindow::Pixels([200, 300, 0, 5]).rect(160, 90).is_err());
+  }
+
+  #[test]
+  fn test_pfm_round_trip() {
+    let mut canvas = Canvas::new(3, 2);
+    canvas.draw(0, 0, &Color::new(-1.5, 2.0, 1e6));
+    canvas.draw(2, 1, &Color::new(0.25, -0.0, -7.0));
+    let mut data = Vec::new();
+    canvas.write_pfm(&mut data).unwrap();
+    let loaded = Canvas::read_pfm(&data[..]).unwrap();
+    assert_eq!((loaded.width(), loaded.height()), (3, 2));
+    for &(x, y) in &[(0, 0), (2, 1), (1, 0)] {
+      let (a, b) = (canvas.get(x, y), loaded.get(x, y));
+      assert_eq!((a.r(), a.g(), a.b()), (b.r(), b.g(), b.b()));
+    }
+    // Sizes too big to read are errors, not overflows.
+    let huge = b"PF\n4294967295 4294967295\n-1\n";
+    assert!(Canvas::read_pfm(&huge[..]).is_err());
+  }
+
+  #[test]
+  fn test_png_round_trip() {
+    for x in 0..=255 {
+      let p = image::Rgb([x, x, x]);
+      assert_eq!(Canvas::to_rgb(&Canvas::from_rgb(&p)), p);
+    }
+  }
 }
@@ -0,0 +1,166 @@
+// Captions burned into rendered images, such as which frame they are and how
+// long they took to render, and the same facts as metadata saved with them.
+use crate::canvas::*;
+use crate::vec3::*;
+use rusttype::{point, Font, Scale};
+use std::time::Duration;
+
+type T = f32;
+
+// Facts about a render, for its caption and metadata.
+pub struct RenderInfo<'a> {
+  pub scene: &'a str,
+  // None for a still image.
+  pub frame: Option<u32>,
+  pub samples_per_pixel: u32,
+  pub render_time: Duration,
+}
+
+impl RenderInfo<'_> {
+  // The facts as keywords and text for PNG tEXt chunks.
+  pub fn metadata(&self) -> Vec<(String, String)> {
+    let mut metadata = vec![
+      ("Software".to_string(), "raytracer".to_string()),
+      ("Scene".to_string(), self.scene.to_string()),
+    ];
+    if let Some(frame) = self.frame {
+      metadata.push(("Frame".to_string(), frame.to_string()));
+    }
+    metadata.push((
+      "Samples per pixel".to_string(),
+      self.samples_per_pixel.to_string(),
+    ));
+    metadata.push(("Render time".to_string(), self.time()));
+    metadata
+  }
+
+  fn time(&self) -> String {
+    format!("{:.1} s", self.render_time.as_secs_f32())
+  }
+
+  // The text with {scene}, {frame}, {spp} and {time} replaced by the facts
+  // they name. Still images' frame is "-".
+  fn fill(&self, text: &str) -> String {
+    let frame = self.frame.map_or("-".to_string(), |f| f.to_string());
+    text
+      .replace("{scene}", self.scene)
+      .replace("{frame}", &frame)
+      .replace("{spp}", &self.samples_per_pixel.to_string())
+      .replace("{time}", &self.time())
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Corner {
+  TopLeft,
+  TopRight,
+  BottomLeft,
+  BottomRight,
+}
+
+#[derive(Clone, Debug)]
+pub struct Caption {
+  // The text, with placeholders for RenderInfo::fill.
+  pub text: String,
+  // A TrueType or OpenType font file, which --caption-font must give.
+  pub font: String,
+  // The height of a line, in pixels.
+  pub size: T,
+  // Red, green and blue, from 0 to 1.
+  pub color: [T; 3],
+  pub corner: Corner,
+}
+
+impl Default for Caption {
+  fn default() -> Caption {
+    Caption {
+      text: "{scene}  frame {frame}  {spp} spp  {time}".to_string(),
+      font: String::new(),
+      size: 16.0,
+      color: [1.0, 1.0, 1.0],
+      corner: Corner::BottomLeft,
+    }
+  }
+}
+
+impl Caption {
+  pub fn load_font(&self) -> Result<Font<'static>, String> {
+    let name = &self.font;
+    let data = std::fs::read(name).map_err(|e| format!("{}: {}", name, e))?;
+    Font::try_from_vec(data).ok_or_else(|| format!("{}: Bad font", name))
+  }
+
+  // Draws the caption over the image, half a line in from its corner.
+  pub fn burn_in(&self, font: &Font, image: &mut Canvas, info: &RenderInfo) {
+    let text = info.fill(&self.text);
+    // The color is as it's displayed, and saving takes the square root of
+    // the film's linear values, so blend in its square.
+    let [r, g, b] = self.color;
+    let ink = Color::new(r * r, g * g, b * b);
+    let scale = Scale::uniform(self.size);
+    let metrics = font.v_metrics(scale);
+    let glyphs: Vec<_> = font
+      .layout(&text, scale, point(0.0, metrics.ascent))
+      .collect();
+    let width = glyphs.last().map_or(0.0, |g| {
+      g.position().x + g.unpositioned().h_metrics().advance_width
+    });
+    let height = metrics.ascent - metrics.descent;
+    let margin = 0.5 * self.size;
+    let (image_width, image_height) = (image.width(), image.height());
+    // The top left corner of the text, in pixels from the image's top left.
+    let left = match self.corner {
+      Corner::TopLeft | Corner::BottomLeft => margin,
+      Corner::TopRight | Corner::BottomRight => {
+        image_width as T - margin - width
+      }
+    };
+    let top = match self.corner {
+      Corner::TopLeft | Corner::TopRight => margin,
+      Corner::BottomLeft | Corner::BottomRight => {
+        image_height as T - margin - height
+      }
+    };
+    let (left, top) = (left.round() as i32, top.round() as i32);
+    for glyph in &glyphs {
+      let bounds = match glyph.pixel_bounding_box() {
+        Some(bounds) => bounds,
+        None => continue,
+      };
+      glyph.draw(|x, y, coverage| {
+        let x = left + bounds.min.x + x as i32;
+        let row = top + bounds.min.y + y as i32;
+        let inside = (0..image_width as i32).contains(&x)
+          && (0..image_height as i32).contains(&row);
+        if !inside {
+          return;
+        }
+        // Canvas rows count up from the bottom.
+        let (x, y) = (x as u32, image_height - 1 - row as u32);
+        let color = (1.0 - coverage) * image.get(x, y) + coverage * ink;
+        image.draw(x, y, &color);
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fill() {
+    let info = RenderInfo {
+      scene: "spheres",
+      frame: Some(12),
+      samples_per_pixel: 64,
+      render_time: Duration::from_millis(2500),
+    };
+    assert_eq!(
+      info.fill("{scene} {frame}: {spp} spp in {time}"),
+      "spheres 12: 64 spp in 2.5 s"
+    );
+    let metadata = info.metadata();
+    assert!(metadata.contains(&("Frame".to_string(), "12".to_string())));
+  }
+}
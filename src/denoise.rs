@@ -0,0 +1,289 @@
+// Denoisers for the film, which smooth out the noise of renders with few
+// samples per pixel. Both are guided by the albedo, normal and depth of what
+// each pixel sees, so they don't blur across edges, and by the variance of
+// its samples, so they blur noisy pixels more than clean ones. They filter
+// the light arriving at surfaces rather than the image, dividing the albedo
+// out first and multiplying it back in after, so textures stay sharp.
+use crate::aov::*;
+use crate::canvas::*;
+use crate::vec3::*;
+
+type T = f32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Denoiser {
+  // Dammertz et al.'s edge-avoiding a-trous wavelet transform: repeated
+  // 5x5 blurs whose taps spread twice as far each time, for a wide blur at
+  // little cost.
+  ATrous,
+  // Non-local means: each pixel averages those in a window around it whose
+  // own neighborhoods look like its own.
+  NonLocalMeans,
+}
+
+// The AOVs the denoisers are guided by.
+pub const GUIDES: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+pub struct Guides<'a> {
+  pub albedo: &'a Canvas,
+  pub normal: &'a Canvas,
+  pub depth: &'a Canvas,
+  // The variance of each pixel's luminance, as an estimate of its mean.
+  pub variance: &'a Canvas,
+}
+
+// A region's pixels, in rows from the bottom, with their guides.
+struct Features {
+  width: usize,
+  height: usize,
+  albedo: Vec<Vec3>,
+  normal: Vec<Vec3>,
+  depth: Vec<T>,
+  // The variance of the filtered values' luminance.
+  variance: Vec<T>,
+}
+
+impl Features {
+  // How alike the surfaces at two pixels are, from 0 to 1, for pixels
+  // spread apart.
+  fn similarity(&self, p: usize, q: usize, spread: T) -> T {
+    if p == q {
+      return 1.0;
+    }
+    let normal = self.normal[p].dot(self.normal[q]).max(0.0).powi(32);
+    let (dp, dq) = (self.depth[p], self.depth[q]);
+    let tolerance = 0.02 * spread * dp.max(dq) + 1e-4;
+    normal * (-(dp - dq).abs() / tolerance).exp()
+  }
+}
+
+impl Denoiser {
+  // Denoises the region of the image, which the guides cover too. Pixels
+  // outside it are left alone, and don't affect those inside.
+  pub fn denoise(
+    &self,
+    image: &mut Canvas,
+    guides: &Guides,
+    region: PixelRect,
+  ) {
+    let (width, height) = (region.width() as usize, region.height() as usize);
+    let pixels = |canvas: &Canvas| {
+      let mut values = Vec::with_capacity(width * height);
+      for y in region.y0..region.y1 {
+        for x in region.x0..region.x1 {
+          values.push(canvas.get(x, y).0);
+        }
+      }
+      values
+    };
+    // Pixels that see nothing have no albedo, and aren't demodulated.
+    let albedo: Vec<_> = pixels(guides.albedo)
+      .into_iter()
+      .map(|a| {
+        if a.max_element() > 0.0 {
+          a.max(Vec3::new(0.01, 0.01, 0.01))
+        } else {
+          Vec3::new(1.0, 1.0, 1.0)
+        }
+      })
+      .collect();
+    let features = Features {
+      width,
+      height,
+      normal: pixels(guides.normal)
+        .into_iter()
+        .map(|n| {
+          // Averaged normals are short at edges, where they turn.
+          if n.norm_squared() > 1e-6 {
+            n.normalize()
+          } else {
+            n
+          }
+        })
+        .collect(),
+      depth: pixels(guides.depth).iter().map(|d| d.x()).collect(),
+      variance: pixels(guides.variance)
+        .iter()
+        .zip(&albedo)
+        .map(|(v, &a)| v.x() / Color(a).luminance().powi(2))
+        .collect(),
+      albedo,
+    };
+    let irradiance: Vec<_> = pixels(image)
+      .iter()
+      .zip(&features.albedo)
+      .map(|(&c, &a)| c * (1.0 / a))
+      .collect();
+    let filtered = match self {
+      Denoiser::ATrous => a_trous(irradiance, &features),
+      Denoiser::NonLocalMeans => non_local_means(&irradiance, &features),
+    };
+    for (k, value) in filtered.into_iter().enumerate() {
+      let (x, y) = (k % width, k / width);
+      let color = Color(value * features.albedo[k]);
+      image.draw(region.x0 + x as u32, region.y0 + y as u32, &color);
+    }
+  }
+}
+
+// As in Schied et al.'s spatiotemporal variance-guided filter, without the
+// temporal part. Differences in luminance are measured in standard
+// deviations of the noise, which shrinks as the passes average it out.
+fn a_trous(mut values: Vec<Vec3>, features: &Features) -> Vec<Vec3> {
+  const KERNEL: [T; 5] =
+    [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+  const SIGMA: T = 4.0;
+  let (width, height) = (features.width as i64, features.height as i64);
+  let mut variance = features.variance.clone();
+  for iteration in 0..5 {
+    let step = 1 << iteration;
+    let mut next = Vec::with_capacity(values.len());
+    let mut next_variance = Vec::with_capacity(values.len());
+    for y in 0..height {
+      for x in 0..width {
+        let p = (y * width + x) as usize;
+        let luminance = Color(values[p]).luminance();
+        let deviation = SIGMA * variance[p].sqrt() + 1e-4;
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let (mut total, mut variance_sum) = (0.0, 0.0);
+        for (j, kj) in KERNEL.iter().enumerate() {
+          let qy = y + (j as i64 - 2) * step;
+          if qy < 0 || qy >= height {
+            continue;
+          }
+          for (i, ki) in KERNEL.iter().enumerate() {
+            let qx = x + (i as i64 - 2) * step;
+            if qx < 0 || qx >= width {
+              continue;
+            }
+            let q = (qy * width + qx) as usize;
+            let difference = (Color(values[q]).luminance() - luminance).abs();
+            let surface = features.similarity(p, q, step as T);
+            let weight = kj * ki * surface * (-difference / deviation).exp();
+            sum += weight * values[q];
+            total += weight;
+            variance_sum += weight * weight * variance[q];
+          }
+        }
+        next.push(sum / total);
+        next_variance.push(variance_sum / (total * total));
+      }
+    }
+    values = next;
+    variance = next_variance;
+  }
+  values
+}
+
+// Rousselle et al.'s non-local means for Monte Carlo renders, whose patch
+// distances allow for each pixel's variance.
+fn non_local_means(values: &[Vec3], features: &Features) -> Vec<Vec3> {
+  const SEARCH: i64 = 6;
+  const PATCH: i64 = 1;
+  const K: T = 0.45;
+  let (width, height) = (features.width as i64, features.height as i64);
+  let at = |x: i64, y: i64| {
+    (y.clamp(0, height - 1) * width + x.clamp(0, width - 1)) as usize
+  };
+  let variance = &features.variance;
+  // How far apart two pixels' values are, relative to their noise. Noise
+  // alone puts them about 0 apart.
+  let distance = |p: usize, q: usize| {
+    let (vp, vq) = (variance[p], variance[q]);
+    let d = values[p] - values[q];
+    (d.norm_squared() / 3.0 - (vp + vp.min(vq))) / (1e-10 + K * K * (vp + vq))
+  };
+  let mut filtered = Vec::with_capacity(values.len());
+  for y in 0..height {
+    for x in 0..width {
+      let p = at(x, y);
+      let (mut sum, mut total) = (Vec3::new(0.0, 0.0, 0.0), 0.0);
+      for qy in (y - SEARCH).max(0)..(y + SEARCH + 1).min(height) {
+        for qx in (x - SEARCH).max(0)..(x + SEARCH + 1).min(width) {
+          let q = at(qx, qy);
+          let mut patch = 0.0;
+          for dy in -PATCH..=PATCH {
+            for dx in -PATCH..=PATCH {
+              patch += distance(at(x + dx, y + dy), at(qx + dx, qy + dy));
+            }
+          }
+          let patch = patch / ((2 * PATCH + 1) * (2 * PATCH + 1)) as T;
+          let surface = features.similarity(p, q, 1.0);
+          let weight = (-patch.max(0.0)).exp() * surface;
+          sum += weight * values[q];
+          total += weight;
+        }
+      }
+      filtered.push(sum / total);
+    }
+  }
+  filtered
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::rngs::StdRng;
+  use rand::{Rng, SeedableRng};
+
+  #[test]
+  fn test_denoisers_smooth_noise_but_keep_edges() {
+    let (w, h) = (24, 16);
+    let mut albedo = Canvas::new(w, h);
+    let mut normal = Canvas::new(w, h);
+    let mut depth = Canvas::new(w, h);
+    let mut image = Canvas::new(w, h);
+    let mut variance = Canvas::new(w, h);
+    let mut rng = StdRng::seed_from_u64(1);
+    // The left half is a dark wall, the right a bright one facing another
+    // way, both noisy.
+    for y in 0..h {
+      for x in 0..w {
+        let right = x >= w / 2;
+        albedo.draw(x, y, &Color::new(0.5, 0.5, 0.5));
+        let n = if right {
+          (1.0, 0.0, 0.0)
+        } else {
+          (0.0, 0.0, 1.0)
+        };
+        normal.draw(x, y, &Color::new(n.0, n.1, n.2));
+        depth.draw(x, y, &Color::new(2.0, 2.0, 2.0));
+        let level = if right { 0.8 } else { 0.2 };
+        let c = level + rng.gen_range(-0.1..0.1);
+        image.draw(x, y, &Color::new(c, c, c));
+        // That of the uniform noise.
+        let v = 0.01 / 3.0;
+        variance.draw(x, y, &Color::new(v, v, v));
+      }
+    }
+    let guides = Guides {
+      albedo: &albedo,
+      normal: &normal,
+      depth: &depth,
+      variance: &variance,
+    };
+    let region = CropWindow::Pixels([0, w, 0, h]).rect(w, h).unwrap();
+    for &denoiser in &[Denoiser::ATrous, Denoiser::NonLocalMeans] {
+      let mut denoised = Canvas::new(w, h);
+      for y in 0..h {
+        for x in 0..w {
+          denoised.draw(x, y, &image.get(x, y));
+        }
+      }
+      denoiser.denoise(&mut denoised, &guides, region);
+      let spread = |x0: u32, x1: u32| {
+        let values: Vec<T> = (x0..x1)
+          .flat_map(|x| (0..h).map(move |y| (x, y)))
+          .map(|(x, y)| denoised.get(x, y).r())
+          .collect();
+        let max = values.iter().cloned().fold(T::MIN, T::max);
+        let min = values.iter().cloned().fold(T::MAX, T::min);
+        (min, max)
+      };
+      // Each wall comes out flatter than its noise, at its own level.
+      let (left, right) = (spread(0, w / 2), spread(w / 2, w));
+      assert!(left.1 - left.0 < 0.1 && right.1 - right.0 < 0.1);
+      assert!(left.1 < 0.3 && right.0 > 0.7);
+    }
+  }
+}
@@ -0,0 +1,318 @@
+// Image textures, with filtering, wrapping and mip-mapping.
+extern crate image;
+use crate::vec3::*;
+use image::{DynamicImage, GenericImageView, Rgb};
+
+type T = f32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+  // The single closest texel.
+  Nearest,
+  // Interpolates the four closest texels.
+  Bilinear,
+  // Interpolates bilinear lookups on the two mip levels closest to the size
+  // of the lookup's footprint.
+  Trilinear,
+}
+
+// What lookups outside of [0, 1] see.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+  Repeat,
+  Mirror,
+  Clamp,
+}
+
+impl WrapMode {
+  // Maps a texel index to one inside [0, n).
+  fn apply(self, i: i64, n: u32) -> usize {
+    let n = n as i64;
+    let i = match self {
+      WrapMode::Repeat => i.rem_euclid(n),
+      WrapMode::Mirror => {
+        let m = i.rem_euclid(2 * n);
+        if m >= n {
+          2 * n - 1 - m
+        } else {
+          m
+        }
+      }
+      WrapMode::Clamp => i.clamp(0, n - 1),
+    };
+    i as usize
+  }
+}
+
+// How the values stored in an image relate to linear light.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+  // Encoded with the sRGB transfer curve, as most 8-bit color images are.
+  Srgb,
+  // Stored as is. Data such as roughness or normals, and float images, are
+  // linear.
+  Linear,
+}
+
+impl ColorSpace {
+  // Maps a stored value in [0, 1] to linear light.
+  fn decode(self, x: T) -> T {
+    match self {
+      ColorSpace::Srgb => {
+        if x <= 0.04045 {
+          x / 12.92
+        } else {
+          ((x + 0.055) / 1.055).powf(2.4)
+        }
+      }
+      ColorSpace::Linear => x,
+    }
+  }
+}
+
+struct MipLevel {
+  width: u32,
+  height: u32,
+  texels: Vec<Color>,
+}
+
+impl MipLevel {
+  fn texel(&self, x: usize, y: usize) -> Color {
+    self.texels[y * self.width as usize + x]
+  }
+
+  // Halves each dimension, averaging the texels that collapse into one.
+  fn downsample(&self) -> MipLevel {
+    let width = (self.width / 2).max(1);
+    let height = (self.height / 2).max(1);
+    let mut texels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+      for x in 0..width {
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+          let sx = (2 * x + dx).min(self.width - 1);
+          let sy = (2 * y + dy).min(self.height - 1);
+          sum += self.texel(sx as usize, sy as usize);
+        }
+        texels.push(0.25 * sum);
+      }
+    }
+    MipLevel {
+      width,
+      height,
+      texels,
+    }
+  }
+}
+
+pub struct ImageTexture {
+  // The full resolution image first, then each successively halved level.
+  // Only trilinear filtering builds more than the first level.
+  levels: Vec<MipLevel>,
+  filter: Filter,
+  wrap: WrapMode,
+}
+
+impl ImageTexture {
+  // An 8 or 16-bit image, whose values are decoded from color_space.
+  pub fn new(
+    image: &DynamicImage,
+    color_space: ColorSpace,
+    filter: Filter,
+    wrap: WrapMode,
+  ) -> ImageTexture {
+    let decode = |p: [T; 3]| {
+      let [r, g, b] = p.map(|c| color_space.decode(c));
+      Color::new(r, g, b)
+    };
+    let texels = match image {
+      DynamicImage::ImageLuma16(_)
+      | DynamicImage::ImageLumaA16(_)
+      | DynamicImage::ImageRgb16(_)
+      | DynamicImage::ImageRgba16(_) => {
+        let scale = 1.0 / 65535.0;
+        image
+          .to_rgb16()
+          .pixels()
+          .map(|p| decode([p[0], p[1], p[2]].map(|c| scale * c as T)))
+          .collect()
+      }
+      _ => {
+        let scale = 1.0 / 255.0;
+        image
+          .to_rgb8()
+          .pixels()
+          .map(|p| decode([p[0], p[1], p[2]].map(|c| scale * c as T)))
+          .collect()
+      }
+    };
+    ImageTexture::from_texels(
+      image.width(),
+      image.height(),
+      texels,
+      filter,
+      wrap,
+    )
+  }
+
+  // A float image, such as a Radiance HDR file, in row order from the top.
+  // Its values are linear, and may be above one.
+  pub fn from_hdr(
+    width: u32,
+    height: u32,
+    pixels: &[Rgb<f32>],
+    filter: Filter,
+    wrap: WrapMode,
+  ) -> ImageTexture {
+    let texels = pixels
+      .iter()
+      .map(|p| Color::new(p[0] as T, p[1] as T, p[2] as T))
+      .collect();
+    ImageTexture::from_texels(width, height, texels, filter, wrap)
+  }
+
+  fn from_texels(
+    width: u32,
+    height: u32,
+    texels: Vec<Color>,
+    filter: Filter,
+    wrap: WrapMode,
+  ) -> ImageTexture {
+    let mut levels = vec![MipLevel {
+      width,
+      height,
+      texels,
+    }];
+    if filter == Filter::Trilinear {
+      loop {
+        let last = levels.last().unwrap();
+        if last.width == 1 && last.height == 1 {
+          break;
+        }
+        let next = last.downsample();
+        levels.push(next);
+      }
+    }
+    ImageTexture {
+      levels,
+      filter,
+      wrap,
+    }
+  }
+
+  // Looks up the color at (u, v), where v = 1 is the top of the image.
+  // (du, dv) is how much of the image the lookup covers along each axis,
+  // which selects the mip level when filtering trilinearly.
+  pub fn value(&self, u: T, v: T, du: T, dv: T) -> Color {
+    let v = 1.0 - v;
+    match self.filter {
+      Filter::Nearest => self.nearest(0, u, v),
+      Filter::Bilinear => self.bilinear(0, u, v),
+      Filter::Trilinear => {
+        let base = &self.levels[0];
+        let texels = (du * base.width as T).max(dv * base.height as T);
+        let max_level = (self.levels.len() - 1) as T;
+        let level = texels.max(1.0).log2().min(max_level);
+        let lo = level.floor();
+        let t = level - lo;
+        let lo = lo as usize;
+        if t == 0.0 {
+          return self.bilinear(lo, u, v);
+        }
+        (1.0 - t) * self.bilinear(lo, u, v) + t * self.bilinear(lo + 1, u, v)
+      }
+    }
+  }
+
+  fn nearest(&self, level: usize, u: T, v: T) -> Color {
+    let l = &self.levels[level];
+    let x = self.wrap.apply((u * l.width as T).floor() as i64, l.width);
+    let y = self
+      .wrap
+      .apply((v * l.height as T).floor() as i64, l.height);
+    l.texel(x, y)
+  }
+
+  fn bilinear(&self, level: usize, u: T, v: T) -> Color {
+    let l = &self.levels[level];
+    // Texel centers are at half-integer coordinates.
+    let x = u * l.width as T - 0.5;
+    let y = v * l.height as T - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let texel = |dx: i64, dy: i64| {
+      l.texel(
+        self.wrap.apply(x0 + dx, l.width),
+        self.wrap.apply(y0 + dy, l.height),
+      )
+    };
+    (1.0 - ty) * ((1.0 - tx) * texel(0, 0) + tx * texel(1, 0))
+      + ty * ((1.0 - tx) * texel(0, 1) + tx * texel(1, 1))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn checkerboard() -> DynamicImage {
+    DynamicImage::ImageRgb8(image::RgbImage::from_fn(4, 4, |x, y| {
+      if (x + y) % 2 == 0 {
+        Rgb([255, 255, 255])
+      } else {
+        Rgb([0, 0, 0])
+      }
+    }))
+  }
+
+  #[test]
+  fn test_wrap_modes() {
+    assert_eq!(WrapMode::Repeat.apply(-1, 4), 3);
+    assert_eq!(WrapMode::Repeat.apply(9, 4), 1);
+    assert_eq!(WrapMode::Mirror.apply(-1, 4), 0);
+    assert_eq!(WrapMode::Mirror.apply(5, 4), 2);
+    assert_eq!(WrapMode::Clamp.apply(-3, 4), 0);
+    assert_eq!(WrapMode::Clamp.apply(7, 4), 3);
+  }
+
+  #[test]
+  fn test_wide_footprints_average_the_image() {
+    let texture = ImageTexture::new(
+      &checkerboard(),
+      ColorSpace::Srgb,
+      Filter::Trilinear,
+      WrapMode::Repeat,
+    );
+    assert_eq!(texture.levels.len(), 3);
+    let c = texture.value(0.3, 0.6, 1.0, 1.0);
+    assert!((c.r() - 0.5).abs() < 1e-6);
+    // A tiny footprint sees individual texels.
+    let c = texture.value(0.125, 0.875, 0.0, 0.0);
+    assert!((c.r() - 1.0).abs() < 1e-6);
+  }
+
+  #[test]
+  fn test_color_spaces() {
+    let gray = |image: DynamicImage, color_space: ColorSpace| {
+      ImageTexture::new(&image, color_space, Filter::Nearest, WrapMode::Clamp)
+        .value(0.5, 0.5, 0.0, 0.0)
+        .r()
+    };
+    let srgb = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+      1,
+      1,
+      Rgb([128, 128, 128]),
+    ));
+    // sRGB middle gray is about a fifth of the light of white.
+    assert!((gray(srgb.clone(), ColorSpace::Srgb) - 0.2158).abs() < 1e-3);
+    assert!((gray(srgb, ColorSpace::Linear) - 128.0 / 255.0).abs() < 1e-6);
+    // 16-bit images keep their extra precision.
+    let deep = DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(
+      1,
+      1,
+      Rgb([1000u16, 1000, 1000]),
+    ));
+    assert!((gray(deep, ColorSpace::Linear) - 1000.0 / 65535.0).abs() < 1e-7);
+  }
+}
@@ -0,0 +1,357 @@
+// A camera lens made of real elements, as in pbrt's RealisticCamera. Rays
+// leave the film and refract through each element's surfaces in turn, so
+// the image gets the lens's vignetting, distortion and aberrations, and its
+// field of view changes as it focuses.
+//
+// The lens sits on the z axis, with the film at z = 0 and the scene toward
+// +z. Prescriptions are in millimetres, and scene units are metres.
+use crate::material2::refract;
+use crate::vec3::*;
+use rand::Rng;
+use std::f32::consts::PI;
+
+type T = f32;
+
+// The height of a 35mm film frame, in metres.
+const FILM_HEIGHT: T = 0.024;
+
+#[derive(Clone, Copy, Debug)]
+struct Interface {
+  // Positive if the center of curvature is toward the film. Zero for the
+  // aperture stop, which is flat.
+  radius: T,
+  // The distance to the next interface, or to the film for the last one.
+  thickness: T,
+  // Of the medium behind the interface, toward the film.
+  ior: T,
+  aperture_radius: T,
+}
+
+#[derive(Debug)]
+pub struct LensSystem {
+  // From the front of the lens to the back.
+  interfaces: Vec<Interface>,
+  film_width: T,
+  // The distance from the film to the rear principal plane.
+  film_distance: T,
+  // The radius of the disk behind the rear element that rays are aimed
+  // through, which all rays that get through the lens pass.
+  pupil_radius: T,
+  // The fraction of rays from the film's center that get through.
+  transmission: T,
+}
+
+// Parses a prescription: one line per surface, from the front of the lens,
+// of its radius of curvature, its distance to the next surface, the index
+// of refraction behind it and its aperture's diameter, in millimetres. The
+// stop has a radius of 0. Lines starting with # are comments.
+fn parse_prescription(source: &str) -> Result<Vec<Interface>, String> {
+  let mut interfaces = Vec::new();
+  for (number, line) in source.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let error = |e: &str| format!("Line {}: {}", number + 1, e);
+    let values = line
+      .split_whitespace()
+      .map(|x| x.parse::<T>())
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|e| error(&e.to_string()))?;
+    if values.len() != 4 {
+      return Err(error("Expected radius, thickness, ior and aperture"));
+    }
+    let (radius, thickness, ior, aperture) =
+      (values[0], values[1], values[2], values[3]);
+    if thickness < 0.0 || aperture <= 0.0 || (ior != 0.0 && ior < 1.0) {
+      return Err(error("Bad thickness, ior or aperture"));
+    }
+    interfaces.push(Interface {
+      radius: 0.001 * radius,
+      thickness: 0.001 * thickness,
+      ior: if ior == 0.0 { 1.0 } else { ior },
+      aperture_radius: 0.0005 * aperture,
+    });
+  }
+  if interfaces.is_empty() {
+    return Err("The prescription has no surfaces".to_string());
+  }
+  Ok(interfaces)
+}
+
+impl LensSystem {
+  // A lens from a prescription, for an image of the given aspect ratio,
+  // focused focus_distance metres in front of it.
+  pub fn new(
+    prescription: &str,
+    aspect_ratio: T,
+    focus_distance: T,
+  ) -> Result<LensSystem, String> {
+    let mut lens = LensSystem {
+      interfaces: parse_prescription(prescription)?,
+      film_width: aspect_ratio * FILM_HEIGHT,
+      film_distance: 0.0,
+      pupil_radius: 0.0,
+      transmission: 0.0,
+    };
+    lens.focus(focus_distance)?;
+    lens.find_pupil()?;
+    Ok(lens)
+  }
+
+  pub fn load(
+    path: &str,
+    aspect_ratio: T,
+    focus_distance: T,
+  ) -> Result<LensSystem, String> {
+    std::fs::read_to_string(path)
+      .map_err(|e| e.to_string())
+      .and_then(|source| LensSystem::new(&source, aspect_ratio, focus_distance))
+      .map_err(|e| format!("{}: {}", path, e))
+  }
+
+  // The vertical field of view, in radians.
+  pub fn vfov(&self) -> T {
+    2.0 * (0.5 * FILM_HEIGHT / self.film_distance).atan()
+  }
+
+  // The fraction of rays from the film's center that get through. Images
+  // can be divided by it to make up for the blocked rays.
+  pub fn transmission(&self) -> T {
+    self.transmission
+  }
+
+  fn rear_z(&self) -> T {
+    self.interfaces.last().unwrap().thickness
+  }
+
+  fn front_z(&self) -> T {
+    self.interfaces.iter().map(|i| i.thickness).sum()
+  }
+
+  // Traces a ray through the lens, from the film out of the front if
+  // from_film, or the other way. None if the ray is blocked or totally
+  // internally reflected.
+  fn trace(
+    &self,
+    mut origin: Vec3,
+    mut direction: Vec3,
+    from_film: bool,
+  ) -> Option<(Vec3, Vec3)> {
+    let n = self.interfaces.len();
+    // Each interface's vertex is at the sum of its thickness and those of
+    // the interfaces behind it, so it follows from the last one's.
+    let mut z = if from_film { 0.0 } else { self.front_z() };
+    for k in 0..n {
+      let i = if from_film { n - 1 - k } else { k };
+      let interface = &self.interfaces[i];
+      if from_film {
+        z += interface.thickness;
+      }
+      let vertex = z;
+      if !from_film {
+        z -= interface.thickness;
+      }
+      let center = Vec3::new(0.0, 0.0, vertex - interface.radius);
+      let t = if interface.radius == 0.0 {
+        (vertex - origin.z()) / direction.z()
+      } else {
+        // The vertex is on the near side of the sphere when the center is
+        // ahead of the ray along z.
+        let oc = origin - center;
+        let a = direction.norm_squared();
+        let b = oc.dot(direction);
+        let c = oc.norm_squared() - interface.radius * interface.radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+          return None;
+        }
+        let near = (direction.z() > 0.0) != (interface.radius > 0.0);
+        let root = discriminant.sqrt();
+        if near {
+          (-b - root) / a
+        } else {
+          (-b + root) / a
+        }
+      };
+      if t.is_nan() || t <= 0.0 {
+        return None;
+      }
+      let p = origin + t * direction;
+      let r2 = p.x() * p.x() + p.y() * p.y();
+      if r2 > interface.aperture_radius * interface.aperture_radius {
+        return None;
+      }
+      origin = p;
+      if interface.radius != 0.0 {
+        let mut normal = (p - center).normalize();
+        if normal.dot(direction) > 0.0 {
+          normal = -normal;
+        }
+        let front = if i > 0 {
+          self.interfaces[i - 1].ior
+        } else {
+          1.0
+        };
+        let ratio = if from_film {
+          interface.ior / front
+        } else {
+          front / interface.ior
+        };
+        let d = direction.normalize();
+        let cos = (-d).dot(normal).min(1.0);
+        if ratio * ratio * (1.0 - cos * cos) > 1.0 {
+          return None;
+        }
+        direction = refract(d, normal, ratio);
+      }
+    }
+    Some((origin, direction))
+  }
+
+  // Where a ray parallel to the axis, traced from the film or from the
+  // scene, crosses the axis, and where its extension meets its entry, as
+  // z coordinates: the focal point and principal plane on the far side.
+  fn cardinal_points(&self, from_film: bool) -> Result<(T, T), String> {
+    let x = 0.1 * self.interfaces[0].aperture_radius;
+    let (origin, direction) = if from_film {
+      (Vec3::new(x, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))
+    } else {
+      (
+        Vec3::new(x, 0.0, self.front_z() + 1.0),
+        Vec3::new(0.0, 0.0, -1.0),
+      )
+    };
+    let (o, d) = self
+      .trace(origin, direction, from_film)
+      .ok_or("A ray along the axis doesn't get through the lens")?;
+    if d.x() == 0.0 {
+      return Err("The lens doesn't focus".to_string());
+    }
+    let focal_point = o.z() - o.x() / d.x() * d.z();
+    let principal_plane = o.z() + (x - o.x()) / d.x() * d.z();
+    Ok((focal_point, principal_plane))
+  }
+
+  // Moves the lens so that points focus_distance in front of it are in
+  // focus on the film.
+  fn focus(&mut self, focus_distance: T) -> Result<(), String> {
+    let (focal_point, rear_plane) = self.cardinal_points(false)?;
+    let (_, front_plane) = self.cardinal_points(true)?;
+    let focal_length = rear_plane - focal_point;
+    let object_distance = self.front_z() + focus_distance - front_plane;
+    if focal_length <= 0.0 || object_distance <= focal_length {
+      return Err(format!(
+        "Can't focus {} in front of a lens with a focal length of {}",
+        focus_distance, focal_length
+      ));
+    }
+    // The thin lens equation, between the principal planes.
+    let image_distance =
+      focal_length * object_distance / (object_distance - focal_length);
+    let film_z = rear_plane - image_distance;
+    let last = self.interfaces.last_mut().unwrap();
+    last.thickness -= film_z;
+    if last.thickness <= 0.0 {
+      return Err("The film would be inside the lens".to_string());
+    }
+    self.film_distance = image_distance;
+    Ok(())
+  }
+
+  // Finds the smallest disk behind the rear element that holds every ray
+  // getting through the lens from anywhere on the film, by trying rays on
+  // a grid from points across the film.
+  fn find_pupil(&mut self) -> Result<(), String> {
+    let rear_z = self.rear_z();
+    let rear_radius = self.interfaces.last().unwrap().aperture_radius;
+    let diagonal = 0.5 * (self.film_width.hypot(FILM_HEIGHT));
+    let steps = 64;
+    let cell = 2.0 * rear_radius / steps as T;
+    let (mut pupil_radius, mut center_hits) = (0.0, 0);
+    for film in 0..16 {
+      let film_point = Vec3::new(diagonal * film as T / 15.0, 0.0, 0.0);
+      for i in 0..steps {
+        for j in 0..steps {
+          let x = -rear_radius + (i as T + 0.5) * cell;
+          let y = -rear_radius + (j as T + 0.5) * cell;
+          if x * x + y * y > rear_radius * rear_radius {
+            continue;
+          }
+          let hit = self
+            .trace(film_point, Vec3::new(x, y, rear_z) - film_point, true)
+            .is_some();
+          if hit {
+            pupil_radius = (x * x + y * y).sqrt().max(pupil_radius);
+          }
+          if film == 0 {
+            center_hits += hit as u32;
+          }
+        }
+      }
+    }
+    if center_hits == 0 {
+      return Err("No light gets through the lens".to_string());
+    }
+    self.pupil_radius = (pupil_radius + cell).min(rear_radius);
+    // The share of the pupil that the center's rays got through.
+    let pupil_area = PI * self.pupil_radius * self.pupil_radius;
+    self.transmission = (center_hits as T * cell * cell / pupil_area).min(1.0);
+    Ok(())
+  }
+
+  // A ray to the point (s, t) of the image, each from 0 to 1, leaving the
+  // front of the lens, relative to its front vertex. None if the lens
+  // blocks it.
+  pub fn sample_ray(&self, s: T, t: T) -> Option<(Vec3, Vec3)> {
+    let mut rng = rand::thread_rng();
+    // The lens turns the image upside down.
+    let film_point =
+      Vec3::new((0.5 - s) * self.film_width, (0.5 - t) * FILM_HEIGHT, 0.0);
+    let r = self.pupil_radius * rng.gen::<T>().sqrt();
+    let theta = 2.0 * PI * rng.gen::<T>();
+    let pupil_point =
+      Vec3::new(r * theta.cos(), r * theta.sin(), self.rear_z());
+    let (origin, direction) =
+      self.trace(film_point, pupil_point - film_point, true)?;
+    Some((origin - Vec3::new(0.0, 0.0, self.front_z()), direction))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A single biconvex lens with a focal length of 104mm.
+  const SINGLET: &str = "
+    # radius thickness ior aperture
+    103.3 5 1.5 30
+    -103.3 100 1 30
+  ";
+
+  #[test]
+  fn test_focuses_like_a_thin_lens() {
+    // A far focus puts the film near the focal length.
+    let lens = LensSystem::new(SINGLET, 1.5, 1000.0).unwrap();
+    assert!((lens.film_distance - 0.104).abs() < 0.001);
+    // Focusing closer moves the lens out, narrowing the field of view.
+    let close = LensSystem::new(SINGLET, 1.5, 0.5).unwrap();
+    assert!(close.film_distance > lens.film_distance + 0.02);
+    assert!(close.vfov() < lens.vfov());
+    assert!(LensSystem::new(SINGLET, 1.5, 0.05).is_err());
+  }
+
+  #[test]
+  fn test_rays_from_the_center_leave_toward_the_scene() {
+    let lens = LensSystem::new(SINGLET, 1.5, 10.0).unwrap();
+    for _ in 0..100 {
+      if let Some((origin, direction)) = lens.sample_ray(0.5, 0.5) {
+        assert!(origin.z().abs() < 0.005 && direction.z() > 0.0);
+        // Nearly parallel, converging on the focus far away.
+        assert!(direction.normalize().z() > 0.99);
+      }
+    }
+    assert!(lens.transmission() > 0.5);
+    assert!(parse_prescription("1 2 3").is_err());
+  }
+}
@@ -1,33 +1,86 @@
 mod camera;
 mod canvas;
+mod caption;
+mod denoise;
+mod post;
+mod regularization;
 mod material2;
+mod image_texture;
+mod lens_system;
+mod mapping;
+mod mesh;
+mod microfacet;
+mod noise;
+mod normal_map;
 mod object;
+mod options;
+mod principled;
+mod quaternion;
 mod ray;
+mod spectrum;
 mod vec3_scalar;
 mod vec3;
 mod aabb;
+mod animation;
+mod aov;
+mod aperture;
+mod assets;
 mod bvh;
 mod texture;
+mod texture_expr;
+mod thin_film;
 
+use crate::animation::*;
+use crate::aov::*;
+use crate::assets::*;
 use crate::camera::*;
 use crate::canvas::*;
+use crate::caption::*;
+use crate::denoise::*;
+use crate::post::*;
+use crate::regularization::*;
+use crate::image_texture::*;
+use crate::lens_system::*;
+use crate::mapping::*;
+use crate::mesh::*;
 use crate::material2::*;
 use crate::object::*;
+use crate::options::*;
+use crate::principled::*;
+use crate::quaternion::*;
 use crate::ray::*;
+use crate::spectrum::*;
 use crate::vec3::*;
 use crate::bvh::*;
+use crate::noise::*;
+use crate::normal_map::*;
 use crate::texture::*;
+use crate::texture_expr::*;
+use crate::thin_film::*;
 
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
+use rand::rngs::ThreadRng;
 use rand::Rng;
+use rusttype::Font;
+use std::f32::consts::PI;
 use std::sync::mpsc::channel;
 use std::sync::Arc;
+use std::time::Instant;
 use threadpool::ThreadPool;
 
 type T = f32;
 
-fn ray_color(r: &Ray, obj: &dyn Object, depth: i32) -> Color {
+// The color seen along a ray. aovs, if given, records the first hit's
+// AOVs.
+fn ray_color(
+  r: &Ray,
+  obj: &dyn Object,
+  depth: i32,
+  regularization: &Regularization,
+  bounces: &mut u32,
+  aovs: Option<(&AovRecorder, &mut [Color])>,
+) -> Color {
   let black = Color::new(0.0, 0.0, 0.0);
   let white = Color::new(1.0, 1.0, 1.0);
   let sky = Color::new(0.5, 0.7, 1.0);
@@ -42,43 +95,175 @@ fn ray_color(r: &Ray, obj: &dyn Object, depth: i32) -> Color {
     }
     Some(hr) => {
       let payload = hr.obj.hit_payload(hr.t, r);
-      match payload.material.scatter(r, &payload) {
+      if let Some((recorder, values)) = aovs {
+        recorder.record(&hr, &payload, r, values);
+      }
+      let min_roughness = regularization.min_roughness(*bounces);
+      match payload.material.scatter(r, &payload, min_roughness) {
         None => black,
-        Some(sr) => sr.attenuation * ray_color(&sr.scattered_ray, obj, depth - 1),
+        Some(sr) => {
+          *bounces += 1;
+          let scattered = &sr.scattered_ray;
+          let incoming =
+            ray_color(scattered, obj, depth - 1, regularization, bounces, None);
+          let absorbed = r.media.transmittance(hr.t * r.direction.norm());
+          absorbed * sr.attenuation * incoming
+        }
+      }
+    },
+  }
+}
+
+// Like ray_color, but carrying a handful of wavelengths along the path instead
+// of RGB. The ray's wavelength must be the hero wavelength of wavelengths.
+fn ray_color_spectral(
+  r: &Ray,
+  obj: &dyn Object,
+  depth: i32,
+  wavelengths: &mut SampledWavelengths,
+  regularization: &Regularization,
+  bounces: &mut u32,
+  aovs: Option<(&AovRecorder, &mut [Color])>,
+) -> SampledSpectrum {
+  let black = SampledSpectrum::constant(0.0);
+  let white = Color::new(1.0, 1.0, 1.0);
+  let sky = Color::new(0.5, 0.7, 1.0);
+  if depth <= 0 {
+    return black;
+  }
+  match obj.hit(0.001, T::INFINITY, r) {
+    None => {
+      let unit = r.direction / r.direction.norm();
+      let t = 0.5 * (unit.y() + 1.0);
+      SampledSpectrum::from_rgb((1.0 - t) * white + t * sky, wavelengths)
+    }
+    Some(hr) => {
+      let payload = hr.obj.hit_payload(hr.t, r);
+      if let Some((recorder, values)) = aovs {
+        recorder.record(&hr, &payload, r, values);
+      }
+      if payload.material.is_dispersive() {
+        wavelengths.terminate_secondary();
+      }
+      let min_roughness = regularization.min_roughness(*bounces);
+      match payload.material.scatter(r, &payload, min_roughness) {
+        None => black,
+        Some(sr) => {
+          *bounces += 1;
+          let absorbed = r.media.transmittance(hr.t * r.direction.norm());
+          let attenuation =
+            SampledSpectrum::from_rgb(absorbed * sr.attenuation, wavelengths);
+          attenuation
+            * ray_color_spectral(
+              &sr.scattered_ray,
+              obj,
+              depth - 1,
+              wavelengths,
+              regularization,
+              bounces,
+              None,
+            )
+        }
       }
     },
   }
 }
 
 struct World {
+  // The scene's name, for captions and metadata.
+  pub name: &'static str,
   pub objects: ObjectList,
-  pub bvh: BVHNode
+  pub bvh: BVHNode,
+  pub ids: SceneIds,
 }
 
 impl World {
-  pub fn new() -> World {
+  pub fn new(name: &'static str) -> World {
     World {
+      name,
       objects: ObjectList::new(),
-      bvh: BVHNode::new()
+      bvh: BVHNode::new(),
+      ids: SceneIds::default(),
     }
   }
-  fn create_bvh(&mut self) {
+  fn create_bvh(&mut self, time0: T, time1: T) {
+    let objects = self.objects.objects.iter().flatten();
+    for (object, &index) in objects.zip(&self.objects.indices) {
+      self.ids.add(&**object, index);
+    }
     self.bvh = BVHNode::new_from_objects(
         &mut self.objects.objects[..],
-        0.0, 1.0)
+        time0, time1)
+  }
+}
+
+// A striped flag hung from its left edge at corner, rippling in the wind
+// for four seconds.
+fn flag(corner: Point) -> Mesh {
+  let (columns, rows) = (16, 10);
+  let (width, height) = (1.2, 0.8);
+  let mut uvs = Vec::new();
+  let mut triangles = Vec::new();
+  for j in 0..=rows {
+    for i in 0..=columns {
+      uvs.push((i as T / columns as T, j as T / rows as T));
+      if i < columns && j < rows {
+        let v = j * (columns + 1) + i;
+        let above = v + columns + 1;
+        triangles.push([v, v + 1, above + 1]);
+        triangles.push([v, above + 1, above]);
+      }
+    }
   }
+  // Waves run out from the pole, growing toward the free edge.
+  let shape = |time: T| -> Vec<Point> {
+    uvs
+      .iter()
+      .map(|&(u, v)| {
+        let phase = 2.0 * PI * (1.5 * time - 1.2 * u);
+        corner + Vec3::new(width * u, height * v, 0.15 * u * phase.sin())
+      })
+      .collect()
+  };
+  let steps = (0..=32).map(|k| shape(k as T / 8.0)).collect();
+  let stripes = Texture::new_checkers(
+    Texture::Color(Color::new(0.7, 0.05, 0.05)),
+    Texture::Color(Color::new(0.9, 0.9, 0.9)),
+    0.2,
+  )
+  .in_space(TextureSpace::Uv);
+  Mesh::new(
+    shape(0.0),
+    uvs,
+    triangles,
+    Material::new_lambertian(stripes),
+  )
+  .with_motion(steps, 0.0, 4.0)
 }
 
-fn make_world() -> World {
+// The world for a render whose shutter is open from time0 to time1.
+fn make_world(options: &Options, time0: T, time1: T) -> Result<World, String> {
   let mut rng = rand::thread_rng();
-  let mut world = World::new();
-  let checkers = Texture::Checkers(
-      Arc::new(Texture::Color(Color::new(0.2, 0.3, 0.1))),
-      Arc::new(Texture::Color(Color::new(0.9, 0.9, 0.9))));
+  let showcase = options.scene == Scene::Showcase;
+  let mut world = World::new(options.scene.name());
+  let mut assets = Assets::new(options.texture_filter, options.texture_wrap);
+  let ground = match options.ground_texture {
+    Some(ref source) => {
+      parse_texture(source, &mut |path, color_space| {
+        assets.image(path, color_space)
+      })
+      .map_err(|e| format!("Bad ground texture: {}", e))?
+    }
+    None => Texture::new_checkers(
+      Texture::Color(Color::new(0.2, 0.3, 0.1)),
+      Texture::Color(Color::new(0.9, 0.9, 0.9)),
+      0.1 * PI,
+    ),
+  };
   world.objects.add(Box::new(Sphere::new(
     Point::new(0.0, -1000.0, 0.0),
     1000.0,
-    Material::new_lambertian(checkers),
+    Material::new_lambertian(ground),
   )));
 
   let image_central_point = Point::new(4.0, 0.2, 0.0);
@@ -93,7 +278,14 @@ fn make_world() -> World {
       if (sphere_center - image_central_point).norm() <= 0.9 {
         continue;
       }
-      if choose_mat < 0.5 {
+      if showcase {
+        world.objects.add(showcase_sphere(
+          choose_mat,
+          sphere_center,
+          &mut rng,
+          &mut assets,
+        )?);
+      } else if choose_mat < 0.5 {
         let center2 = sphere_center; // + Vec3::new(0.0, rng.gen_range(0.0 .. 0.0), 0.0);
         let albedo = Color::random() * Color::random();
         world.objects.add(Box::new(MovingSphere::new(
@@ -113,111 +305,711 @@ fn make_world() -> World {
           Material::new_metal(Texture::Color(albedo), fuzz),
         )));
       } else {
-        world.objects.add(Box::new(Sphere::new(
-          sphere_center,
-          0.2,
-          Material::new_dielectric(1.5),
-        )));
+        let glass = assets.material("glass", || Material::new_dielectric(1.5));
+        world.objects.add(Box::new(Sphere::new(sphere_center, 0.2, glass)));
       }
     }
   }
 
+  let glass = if showcase {
+    Material::new_dispersive_dielectric(
+      RefractionIndex::diamond(),
+      Color::new(0.0, 0.0, 0.0),
+    )
+  } else {
+    Material::new_dielectric(1.5)
+  };
   world.objects.add(Box::new(Sphere::new(
     Point::new(2.0, 1.0, 0.0),
     1.0,
-    Material::new_dielectric(1.5),
+    glass,
   )));
 
-  let earth_texture = Texture::from_image_filename("./earthmap.jpg");
-  world.objects.add(Box::new(Sphere::new(
-    Point::new(0.0, 1.0, 0.0),
-    1.0,
-    Material::new_lambertian(earth_texture),
-  )));
+  let earth_texture = assets.image("./earthmap.jpg", ColorSpace::Srgb)?;
+  if showcase {
+    // The earth map doubles as a roughness map, so the dark oceans come out
+    // glossy and the land matte.
+    let mut earth_material = Principled::new(earth_texture);
+    earth_material.roughness =
+      assets.image("./earthmap.jpg", ColorSpace::Linear)?;
+    // After a second the earth starts to spin, a quarter turn a second.
+    let y = Vec3::new(0.0, 1.0, 0.0);
+    let mut spin =
+      Track::new(1.0, Quaternion::identity(), Interpolation::Linear);
+    for turn in 1..4 {
+      let angle = 0.5 * PI * turn as T;
+      spin = spin.with_key(
+        1.0 + turn as T,
+        Quaternion::from_axis_angle(y, angle),
+        Interpolation::Linear,
+      );
+    }
+    let earth = Sphere::new(
+      Point::new(0.0, 0.0, 0.0),
+      1.0,
+      Material::new_principled(earth_material),
+    );
+    world.objects.add(Box::new(
+      Animated::new(Box::new(earth), Track::constant(y)).with_rotation(spin),
+    ));
+  } else {
+    world.objects.add(Box::new(Sphere::new(
+      Point::new(0.0, 1.0, 0.0),
+      1.0,
+      Material::new_lambertian(earth_texture),
+    )));
+  }
+  let mut metal =
+    Material::new_metal(Texture::Color(Color::new(0.7, 0.6, 0.5)), 0.0);
+  if showcase {
+    // Tiled, by a normal map.
+    metal = metal.with_normal_map(NormalMap::Tangent(
+      assets.image("./tiles_normal.png", ColorSpace::Linear)?,
+    ));
+  }
   world.objects.add(Box::new(Sphere::new(
     Point::new(-2.0, 1.0, 0.0),
     1.0,
-    Material::new_metal(Texture::Color(Color::new(0.7, 0.6, 0.5)), 0.0),
+    metal,
   )));
 
-  world.create_bvh();
+  // The showcase's extras, around the big spheres.
+  if showcase {
+    // A soap bubble: a film of water with air on both sides. After a second
+    // it drifts up and away.
+    let drift = Track::new(1.0, Vec3::new(0.0, 0.0, 0.0), Interpolation::Bezier)
+      .with_key(2.5, Vec3::new(0.5, 0.6, -0.5), Interpolation::Bezier)
+      .with_key(4.0, Vec3::new(0.3, 1.5, -1.5), Interpolation::Bezier);
+    let bubble = Sphere::new(
+      Point::new(-1.0, 1.7, 2.5),
+      0.45,
+      Material::new_dielectric(1.0).with_thin_film(ThinFilm {
+        thickness: 500.0,
+        refraction_index: 1.33,
+      }),
+    );
+    let swelling = Track::new(1.0, 1.0, Interpolation::Linear).with_key(
+      4.0,
+      1.3,
+      Interpolation::Linear,
+    );
+    world.objects.add(Box::new(
+      Animated::new(Box::new(bubble), drift).with_scale(swelling),
+    ));
+
+    // A chain-link fence behind the big spheres, and a hedge behind that, cut
+    // out of plain quads.
+    world.objects.add(Box::new(Quad::new(
+      Point::new(-4.0, 0.0, -4.0),
+      Vec3::new(0.0, 0.0, 8.0),
+      Vec3::new(0.0, 2.5, 0.0),
+      Material::new_metal(Texture::Color(Color::new(0.6, 0.6, 0.6)), 0.4)
+        .with_cutout(
+          assets.image("./fence_alpha.png", ColorSpace::Linear)?,
+          AlphaMode::Stochastic,
+        ),
+    )));
+    world.objects.add(Box::new(Quad::new(
+      Point::new(-6.0, 0.0, -6.0),
+      Vec3::new(0.0, 0.0, 12.0),
+      Vec3::new(0.0, 3.5, 0.0),
+      Material::new_lambertian(Texture::Color(Color::new(0.1, 0.35, 0.08)))
+        .with_cutout(
+          Texture::new_noise(NoisePattern::Plain, 4.0, 4, rng.gen()),
+          AlphaMode::Threshold(0.5),
+        ),
+    )));
+
+    // A flag waving from the end of the fence.
+    world
+      .objects
+      .add_all(flag(Point::new(-4.0, 1.7, 3.9)).into_triangles());
+  }
+
+  world.create_bvh(time0, time1);
 
-  return world;
+  Ok(world)
 }
 
-fn render_spheres() {
-  let image_width = (2 * 400) as u32;
-  let image_height = (2 * 225) as u32;
+// A small sphere of the showcase, made of one of the materials picked by
+// choose_mat.
+fn showcase_sphere(
+  choose_mat: T,
+  sphere_center: Point,
+  rng: &mut ThreadRng,
+  assets: &mut Assets,
+) -> Result<Box<dyn Object + Sync + Send>, String> {
+  let sphere: Box<dyn Object + Sync + Send> = if choose_mat < 0.06 {
+    let pattern = match rng.gen_range(0..4) {
+      0 => NoisePattern::Plain,
+      1 => NoisePattern::Turbulence,
+      2 => NoisePattern::Marble,
+      _ => NoisePattern::Wood,
+    };
+    let noise = Texture::new_noise(pattern, 8.0, 7, rng.gen())
+      .in_space(TextureSpace::Object);
+    Box::new(Sphere::new(
+      sphere_center,
+      0.2,
+      Material::new_lambertian(noise),
+    ))
+  } else if choose_mat < 0.1 {
+    // Checkers in surface coordinates, under one of the projections.
+    let checkers = Texture::new_checkers(
+      Texture::Color(Color::random()),
+      Texture::Color(Color::random()),
+      0.5,
+    )
+    .in_space(TextureSpace::Uv);
+    let (projection, scale) = match rng.gen_range(0..5) {
+      0 => (Projection::Surface, 8.0),
+      1 => (
+        Projection::Planar {
+          u_axis: Vec3::new(1.0, 0.0, 0.0),
+          v_axis: Vec3::new(0.0, 1.0, 0.0),
+        },
+        10.0,
+      ),
+      2 => (Projection::Spherical, 8.0),
+      3 => (Projection::Cylindrical, 8.0),
+      _ => (Projection::Triplanar { sharpness: 4.0 }, 10.0),
+    };
+    let texture = checkers.mapped(UvMapping {
+      projection,
+      transform: UvTransform {
+        scale: (scale, scale),
+        rotation: rng.gen_range(0.0..PI),
+        ..UvTransform::identity()
+      },
+    });
+    Box::new(Sphere::new(
+      sphere_center,
+      0.2,
+      Material::new_lambertian(texture),
+    ))
+  } else if choose_mat < 0.45 {
+    let center2 = sphere_center; // + Vec3::new(0.0, rng.gen_range(0.0 .. 0.0), 0.0);
+    let albedo = Color::random() * Color::random();
+    Box::new(MovingSphere::new(
+      sphere_center,
+      center2,
+      0.0,
+      1.0,
+      0.2,
+      Material::new_lambertian(Texture::Color(albedo)),
+    ))
+  } else if choose_mat < 0.55 {
+    // Clearcoated paint.
+    let base_color = Color::random() * Color::random();
+    Box::new(Sphere::new(
+      sphere_center,
+      0.2,
+      Material::new_principled(Principled {
+        metallic: Texture::constant(rng.gen_range(0.0..0.5)),
+        roughness: Texture::constant(rng.gen_range(0.2..0.6)),
+        clearcoat: Texture::constant(1.0),
+        ..Principled::new(Texture::Color(base_color))
+      }),
+    ))
+  } else if choose_mat < 0.6 {
+    // Paint worn through to the metal underneath, where turbulence is
+    // low.
+    let paint = Texture::Color(Color::random() * Color::random());
+    let steel = Texture::constant(0.6);
+    let wear = Texture::new_noise(NoisePattern::Turbulence, 6.0, 5, rng.gen())
+      .in_space(TextureSpace::Object)
+      .ramp(&[
+        (0.15, Color::new(1.0, 1.0, 1.0)),
+        (0.2, Color::new(0.0, 0.0, 0.0)),
+      ]);
+    Box::new(Sphere::new(
+      sphere_center,
+      0.2,
+      Material::new_principled(Principled {
+        metallic: wear.clone(),
+        roughness: Texture::mix(
+          Texture::constant(0.5),
+          Texture::constant(0.25),
+          wear.clone(),
+        ),
+        ..Principled::new(Texture::mix(paint, steel, wear))
+      }),
+    ))
+  } else if choose_mat < 0.85 {
+    let albedo = Color::random_range(0.5, 1.0);
+    let roughness = rng.gen_range(0.0..0.5);
+    let anisotropy = rng.gen_range(-0.8..0.8);
+    let mut material = Material::new_conductor(
+      Texture::Color(albedo),
+      Texture::constant(roughness),
+      anisotropy,
+    );
+    if rng.gen_bool(0.3) {
+      // Hammered metal.
+      material = material.with_normal_map(NormalMap::Bump {
+        height: Texture::new_noise(
+          NoisePattern::Turbulence,
+          40.0,
+          2,
+          rng.gen(),
+        ),
+        scale: 0.004,
+      });
+    }
+    Box::new(Sphere::new(sphere_center, 0.2, material))
+  } else if choose_mat < 0.9 {
+    let material = if rng.gen() {
+      assets.material("glass", || Material::new_dielectric(1.5))
+    } else {
+      let tint = Color::random_range(0.2, 1.0);
+      Arc::new(Material::new_colored_dielectric(1.5, tint, 0.4)?)
+    };
+    Box::new(Sphere::new(sphere_center, 0.2, material))
+  } else if choose_mat < 0.95 {
+    let flint = assets.material("flint glass", || {
+      Material::new_dispersive_dielectric(
+        RefractionIndex::flint_glass(),
+        Color::new(0.0, 0.0, 0.0),
+      )
+    });
+    Box::new(Sphere::new(sphere_center, 0.2, flint))
+  } else {
+    let roughness = rng.gen_range(0.1..0.4);
+    Box::new(Sphere::new(
+      sphere_center,
+      0.2,
+      Material::new_rough_dielectric(
+        1.5,
+        Color::new(0.0, 0.0, 0.0),
+        Texture::constant(roughness),
+        0.0,
+      ),
+    ))
+  };
+  Ok(sphere)
+}
 
-  let samples_per_pixel = 100 as u32;
+// The camera's path through the scene. It starts where still images are
+// taken from, and in the showcase then swings around the big spheres.
+fn camera_rig(options: &Options) -> CameraRig {
+  let vfov = options.fov.unwrap_or(match options.camera {
+    CameraProjection::Fisheye => 180.0,
+    _ => 20.0,
+  });
+  if options.scene == Scene::Spheres {
+    return CameraRig {
+      position: Track::constant(Point::new(3.0, 2.0, 13.0)),
+      look_at: Track::constant(Point::new(0.0, 0.0, 0.0)),
+      vfov: Track::constant(vfov),
+      focus_distance: Track::constant(10.0),
+    };
+  }
+  CameraRig {
+    position: Track::new(
+      0.0,
+      Point::new(3.0, 2.0, 13.0),
+      Interpolation::Bezier,
+    )
+    .with_key(2.0, Point::new(9.0, 2.5, 9.5), Interpolation::Bezier)
+    .with_key(4.0, Point::new(13.0, 2.0, 3.0), Interpolation::Bezier),
+    look_at: Track::new(0.0, Point::new(0.0, 0.0, 0.0), Interpolation::Linear)
+      .with_key(4.0, Point::new(0.0, 0.5, 0.0), Interpolation::Linear),
+    vfov: Track::constant(vfov),
+    focus_distance: Track::new(0.0, 10.0, Interpolation::Linear).with_key(
+      4.0,
+      12.0,
+      Interpolation::Linear,
+    ),
+  }
+}
 
-  let mut canvas = Canvas::new(image_width, image_height);
-  //let lookfrom = Point::new(13.0, 2.0, 3.0);
-  let lookfrom = Point::new(3.0, 2.0, 13.0);
-  let lookat = Point::new(0.0, 0.0, 0.0);
+// The camera for a frame whose shutter is open from time0 to time1, and
+// what the image's radiance should be scaled by. The camera follows the rig
+// while the shutter is open, if it moves then, so its own motion blurs the
+// image too.
+fn make_camera(
+  options: &Options,
+  rig: &CameraRig,
+  time0: T,
+  time1: T,
+  image_width: u32,
+  image_height: u32,
+) -> Result<(Camera, T), String> {
+  let lookfrom = rig.position.at(time0);
+  let lookat = rig.look_at.at(time0);
   let vup = Vec3::new(0.0, 1.0, 0.0);
   let aspect_ratio = image_width as T / image_height as T;
-  let aperture = 0.05; // 2.0;
-  let dist_to_focus = 10.0; // (lookat - lookfrom).norm();
-  let camera = Camera::new(
+  let dist_to_focus = rig.focus_distance.at(time0);
+  let vfov = rig.vfov.at(time0);
+  let aperture = options.exposure.map_or(0.05, |e| e.aperture(vfov));
+  let mut exposure_scale = options.exposure.map_or(1.0, |e| e.scale());
+  let mut camera = Camera::new(
     lookfrom,
     lookat,
     vup,
-    20.0,
+    vfov,
     aspect_ratio,
     aperture,
     dist_to_focus,
-    0.0,
-    1.0,
-  );
+    time0,
+    time1,
+  )
+  .with_projection(options.camera)
+  .with_aperture(options.aperture.clone())
+  .with_shutter_curve(options.shutter_curve);
+  if !rig.holds_still(time0, time1) {
+    camera = camera.with_rig(Arc::new(rig.clone()));
+  }
+  if let Some(path) = &options.lens {
+    let lens = LensSystem::load(path, aspect_ratio, dist_to_focus)?;
+    exposure_scale /= lens.transmission();
+    camera = camera.with_lens_system(Arc::new(lens));
+  }
+  camera.set_image_height(image_height);
+  Ok((camera, exposure_scale))
+}
 
-  let world = Arc::new(make_world());
+fn render_spheres(options: &Options) -> Result<(), String> {
+  let image_width = (2 * 400) as u32;
+  // Panoramas cover twice as much longitude as latitude.
+  let image_height = match options.camera {
+    CameraProjection::Equirectangular => image_width / 2,
+    _ => (2 * 225) as u32,
+  };
+
+  // Each frame's number and shutter interval.
+  let frames: Vec<(Option<u32>, (T, T))> = match options.frame_range() {
+    Some(range) => (range.first..=range.last)
+      .map(|frame| (Some(frame), range.shutter(frame)))
+      .collect(),
+    None => vec![(None, options.shutter_interval)],
+  };
+  let time0 = frames[0].1 .0;
+  let time1 = frames[frames.len() - 1].1 .1;
+  let world = Arc::new(make_world(options, time0, time1)?);
+
+  let rig = camera_rig(options);
+  let font = match options.caption {
+    Some(ref caption) => Some(caption.load_font()?),
+    None => None,
+  };
+  // One pool for all the frames.
+  let pool = ThreadPool::new(12);
+  for (frame, (open, close)) in frames {
+    let (camera, exposure_scale) =
+      make_camera(options, &rig, open, close, image_width, image_height)?;
+    render_frame(
+      options,
+      &world,
+      &camera,
+      exposure_scale,
+      (image_width, image_height),
+      frame,
+      font.as_ref(),
+      &pool,
+    )?;
+  }
+  Ok(())
+}
+
+// Renders the world through the camera, or both of its eyes, into
+// scene.png, or scene_0001.png and so on for frames of an animation, or a
+// file for each eye. With a crop window, only the pixels in it are
+// rendered. The font is for the caption, if there is one.
+#[allow(clippy::too_many_arguments)]
+fn render_frame(
+  options: &Options,
+  world: &Arc<World>,
+  camera: &Camera,
+  exposure_scale: T,
+  (image_width, image_height): (u32, u32),
+  frame: Option<u32>,
+  font: Option<&Font>,
+  pool: &ThreadPool,
+) -> Result<(), String> {
+  let start = Instant::now();
+  let samples_per_pixel = options.samples_per_pixel;
+  let suffix = frame.map_or(String::new(), |f| format!("_{:04}", f));
 
-  let n_workers = 12;
-  let pool = ThreadPool::new(n_workers);
+  let full = PixelRect {
+    x0: 0,
+    y0: 0,
+    x1: image_width,
+    y1: image_height,
+  };
+  let rect = match options.crop {
+    Some(crop) => crop.rect(image_width, image_height)?,
+    None => full,
+  };
+  // The size of each view's region, and the pixel at its lower left
+  // corner. Crop-only images hold just the crop window.
+  let (origin, (w, h)) = if options.crop_only {
+    ((rect.x0, rect.y0), (rect.width(), rect.height()))
+  } else {
+    ((0, 0), (image_width, image_height))
+  };
 
-  let bar = ProgressBar::new((image_height * image_width).into());
+  // Each view is a camera and where its pixels go: which canvas, and the
+  // lower left corner of its region there.
+  let eye = |eye| camera.clone().for_eye(eye, options.interpupillary_distance);
+  let (sizes, names, views): (Vec<_>, Vec<_>, Vec<_>) = match options.stereo {
+    None => (vec![(w, h)], vec!["scene"], vec![(camera.clone(), 0, 0, 0)]),
+    Some(StereoLayout::SideBySide) => (
+      vec![(2 * w, h)],
+      vec!["scene"],
+      vec![(eye(Eye::Left), 0, 0, 0), (eye(Eye::Right), 0, w, 0)],
+    ),
+    Some(StereoLayout::TopBottom) => (
+      vec![(w, 2 * h)],
+      vec!["scene"],
+      vec![(eye(Eye::Left), 0, 0, h), (eye(Eye::Right), 0, 0, 0)],
+    ),
+    Some(StereoLayout::Separate) => (
+      vec![(w, h), (w, h)],
+      vec!["scene_left", "scene_right"],
+      vec![(eye(Eye::Left), 0, 0, 0), (eye(Eye::Right), 1, 0, 0)],
+    ),
+  };
+  // The beauty image and each AOV is a layer, with a canvas for each of the
+  // views' canvases, saved as scene_normal.png and so on. Denoising needs
+  // its guides, which are only saved if asked for.
+  let mut aovs = options.aovs.clone();
+  if options.denoiser.is_some() {
+    aovs.extend(GUIDES.iter().filter(|guide| !options.aovs.contains(guide)));
+  }
+  let mut layers = vec![(String::new(), options.format)];
+  layers.extend(
+    aovs
+      .iter()
+      .map(|aov| (format!("_{}", aov.name()), aov.format(options.format))),
+  );
+  // And the variance of each pixel's luminance, which tells the denoiser
+  // how noisy it is.
+  let denoising = options.denoiser.is_some();
+  if denoising {
+    layers.push(("_variance".to_string(), "pfm"));
+  }
+  let file_name = |name: &str, layer: &str, format: &str| {
+    format!("{}{}{}.{}", name, layer, suffix, format)
+  };
+  let files: Vec<_> = layers
+    .iter()
+    .flat_map(|(layer, format)| {
+      names.iter().map(move |name| file_name(name, layer, format))
+    })
+    .collect();
+  let saved = (1 + options.aovs.len()) * sizes.len();
+  // Compositing renders into the images already there, which the crop
+  // window then updates.
+  let mut canvases = Vec::new();
+  for (k, (&(w, h), name)) in sizes.iter().cycle().zip(&files).enumerate() {
+    canvases.push(if options.composite && k < saved {
+      let canvas = Canvas::load(name)?;
+      if (canvas.width(), canvas.height()) != (w, h) {
+        return Err(format!(
+          "{} is {}x{}, not {}x{}",
+          name,
+          canvas.width(),
+          canvas.height(),
+          w,
+          h
+        ));
+      }
+      canvas
+    } else {
+      Canvas::new(w, h)
+    });
+  }
+
+  let bar = ProgressBar::new(
+    (views.len() as u32 * rect.height() * rect.width()).into(),
+  );
   bar.set_style(
     ProgressStyle::default_bar()
       .template("[{percent}%] {wide_bar} {pos:>7}/{len:7} [{elapsed}, ETA: {eta}]"),
   );
   bar.set_draw_delta(1000);
 
+  let spectral = options.spectral;
+  let regularization = options.regularization;
   let (tx, rx) = channel();
-  for j in 0..image_height {
-    let my_world = world.clone();
-    let tx = tx.clone();
-    pool.execute(move || {
-      let mut rng = rand::thread_rng();
-      for i in 0..image_width {
-        let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-        for _s in 0..samples_per_pixel {
-          let ri: f32 = rng.gen();
-          let rj: f32 = rng.gen();
-          let u = ((i as T) + ri) / (image_width - 1) as T;
-          let v = ((j as T) + rj) / (image_height - 1) as T;
-          let r = camera.get_ray(u, v);
-
-          pixel_color += ray_color(&r, &my_world.bvh, 50);
+  for &(ref camera, canvas, x0, y0) in &views {
+    for j in rect.y0..rect.y1 {
+      let camera = camera.clone();
+      let my_world = world.clone();
+      let tx = tx.clone();
+      let aovs = aovs.clone();
+      pool.execute(move || {
+        let mut rng = rand::thread_rng();
+        let size = (image_width, image_height);
+        let recorder = AovRecorder::new(&aovs, &my_world.ids, &camera, size);
+        for i in rect.x0..rect.x1 {
+          let mut samples = Vec::with_capacity(samples_per_pixel as usize);
+          // Samples the camera has no ray for count as black.
+          let mut missed = 0;
+          let mut aov_sums = vec![Color::new(0.0, 0.0, 0.0); aovs.len()];
+          let mut aov_values = aov_sums.clone();
+          for s in 0..samples_per_pixel {
+            let ri: f32 = rng.gen();
+            let rj: f32 = rng.gen();
+            let u = ((i as T) + ri) / (image_width - 1) as T;
+            let v = ((j as T) + rj) / (image_height - 1) as T;
+            let mut r = match camera.get_ray(u, v) {
+              Some(r) => r,
+              None => {
+                missed += 1;
+                continue;
+              }
+            };
+
+            let sample;
+            let mut bounces = 0;
+            // Samples that miss everything have black AOVs.
+            aov_values.fill(Color::new(0.0, 0.0, 0.0));
+            if spectral {
+              let mut wavelengths =
+                SampledWavelengths::sample_visible(rng.gen());
+              r.wavelength = Some(wavelengths.hero());
+              let radiance = ray_color_spectral(
+                &r,
+                &my_world.bvh,
+                50,
+                &mut wavelengths,
+                &regularization,
+                &mut bounces,
+                Some((&recorder, &mut aov_values)),
+              );
+              sample = radiance.to_rgb(&wavelengths);
+            } else {
+              let aovs = Some((&recorder, &mut aov_values[..]));
+              let world = &my_world.bvh;
+              let regularization = &regularization;
+              sample =
+                ray_color(&r, world, 50, regularization, &mut bounces, aovs);
+            }
+            recorder.accumulate(&mut aov_sums, &aov_values, s == 0);
+            samples.push(regularization.clamp(sample, bounces));
+          }
+          regularization.reject_outliers(&mut samples);
+          let n = (samples.len() + missed) as f32;
+          let mut pixel_color = Color::new(0.0, 0.0, 0.0);
+          let mut luminance_squared = 0.0;
+          for &sample in &samples {
+            pixel_color += sample;
+            luminance_squared += sample.luminance().powi(2);
+          }
+          pixel_color /= n / exposure_scale;
+          let mut colors = vec![pixel_color];
+          for (aov, sum) in aovs.iter().zip(aov_sums) {
+            colors.push(if aov.blends() {
+              (1.0 / samples_per_pixel as f32) * sum
+            } else {
+              sum
+            });
+          }
+          if denoising {
+            // The variance of the pixel's mean, estimated from its samples.
+            let mean = pixel_color.luminance() / exposure_scale;
+            let variance = (luminance_squared / n - mean * mean).max(0.0)
+              / (n - 1.0).max(1.0)
+              * exposure_scale
+              * exposure_scale;
+            colors.push(Color::new(variance, variance, variance));
+          }
+          let (x, y) = (x0 + i - origin.0, y0 + j - origin.1);
+          tx.send((canvas, x, y, colors)).unwrap();
         }
-        pixel_color /= samples_per_pixel as f32;
-        tx.send((i, j, pixel_color)).unwrap();
-      }
-    })
+      })
+    }
   }
   drop(tx);
 
-  for (i, j, pixel_color) in rx.iter() {
+  for (canvas, i, j, colors) in rx.iter() {
     bar.inc(1);
-    canvas.draw(i, j, &pixel_color);
+    for (layer, color) in colors.iter().enumerate() {
+      canvases[layer * sizes.len() + canvas].draw(i, j, color);
+    }
   }
 
   pool.join();
   bar.finish();
 
-  canvas.save("scene.png");
+  // Where each view's rendered pixels are on its canvas.
+  let regions: Vec<_> = views
+    .iter()
+    .map(|&(_, _, x0, y0)| {
+      let (x0, y0) = (x0 + rect.x0 - origin.0, y0 + rect.y0 - origin.1);
+      PixelRect {
+        x0,
+        y0,
+        x1: x0 + rect.width(),
+        y1: y0 + rect.height(),
+      }
+    })
+    .collect();
+  if let Some(denoiser) = options.denoiser {
+    let (beauty, aov_canvases) = canvases.split_at_mut(sizes.len());
+    if options.keep_noisy {
+      for (image, name) in beauty.iter().zip(&names) {
+        image.save(&file_name(name, "_noisy", options.format), &[])?;
+      }
+    }
+    let layer = |k: usize, canvas| &aov_canvases[k * sizes.len() + canvas];
+    let guide =
+      |aov, canvas| layer(aovs.iter().position(|&a| a == aov).unwrap(), canvas);
+    for (&(_, canvas, _, _), &region) in views.iter().zip(&regions) {
+      let guides = Guides {
+        albedo: guide(Aov::Albedo, canvas),
+        normal: guide(Aov::Normal, canvas),
+        depth: guide(Aov::Depth, canvas),
+        variance: layer(aovs.len(), canvas),
+      };
+      denoiser.denoise(&mut beauty[canvas], &guides, region);
+    }
+  }
+  if !options.post.is_empty() {
+    for (&(_, canvas, x0, y0), &region) in views.iter().zip(&regions) {
+      let bounds = ImageBounds {
+        x: x0 as T - origin.0 as T,
+        y: y0 as T - origin.1 as T,
+        width: image_width as T,
+        height: image_height as T,
+      };
+      options.post.apply(&mut canvases[canvas], region, bounds);
+    }
+  }
+  let info = RenderInfo {
+    scene: world.name,
+    frame,
+    samples_per_pixel,
+    render_time: start.elapsed(),
+  };
+  if let (Some(caption), Some(font)) = (&options.caption, font) {
+    for image in &mut canvases[..sizes.len()] {
+      caption.burn_in(font, image, &info);
+    }
+  }
+  let metadata = info.metadata();
+  for (canvas, name) in canvases.iter().zip(&files).take(saved) {
+    canvas.save(name, &metadata)?;
+  }
+  Ok(())
 }
 
 fn main() {
-  render_spheres();
+  let options = match Options::from_args(std::env::args().skip(1)) {
+    Ok(options) => options,
+    Err(e) => {
+      eprintln!("{}\n\n{}", e, USAGE);
+      std::process::exit(2);
+    }
+  };
+  if options.help {
+    println!("{}", USAGE);
+    return;
+  }
+  if let Err(e) = render_spheres(&options) {
+    eprintln!("{}", e);
+    std::process::exit(1);
+  }
 }
@@ -0,0 +1,227 @@
+// How textures find their coordinates: transforms of surface coordinates,
+// projections that compute them from the hit point instead, and which space
+// procedural textures are evaluated in.
+use crate::texture::*;
+use crate::vec3::*;
+use std::f32::consts::PI;
+
+type T = f32;
+
+// The point procedural textures, such as checkers and noise, are evaluated
+// at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureSpace {
+  // (u, v, 0), so the pattern follows the surface's parameterization.
+  Uv,
+  // The hit point, so the pattern stays put as objects move through it.
+  World,
+  // The hit point relative to the object, so the pattern moves with it.
+  Object,
+}
+
+impl TextureSpace {
+  pub fn point(self, tc: &TexCoord) -> Point {
+    match self {
+      TextureSpace::Uv => Point::new(tc.u, tc.v, 0.0),
+      TextureSpace::World => tc.p,
+      TextureSpace::Object => tc.object_p,
+    }
+  }
+}
+
+// Scales, then rotates (counterclockwise, in radians), then offsets surface
+// coordinates.
+#[derive(Clone, Copy, Debug)]
+pub struct UvTransform {
+  pub scale: (T, T),
+  pub rotation: T,
+  pub offset: (T, T),
+}
+
+impl UvTransform {
+  pub fn identity() -> UvTransform {
+    UvTransform {
+      scale: (1.0, 1.0),
+      rotation: 0.0,
+      offset: (0.0, 0.0),
+    }
+  }
+
+  pub fn apply(&self, tc: &TexCoord) -> TexCoord {
+    let (su, sv) = self.scale;
+    let (u, v) = (su * tc.u, sv * tc.v);
+    let (sin, cos) = self.rotation.sin_cos();
+    let (du, dv) = (su.abs() * tc.du, sv.abs() * tc.dv);
+    // Rotation mixes the two footprints, so take the larger for both.
+    let (du, dv) = if self.rotation == 0.0 {
+      (du, dv)
+    } else {
+      (du.max(dv), du.max(dv))
+    };
+    TexCoord {
+      u: cos * u - sin * v + self.offset.0,
+      v: sin * u + cos * v + self.offset.1,
+      du,
+      dv,
+      ..*tc
+    }
+  }
+}
+
+// Where surface coordinates come from. All projections but Surface work on
+// the hit point in object space.
+#[derive(Clone, Copy, Debug)]
+pub enum Projection {
+  // The object's own surface coordinates.
+  Surface,
+  // u and v are the point's components along each axis. Longer axes repeat
+  // the texture more often.
+  Planar { u_axis: Vec3, v_axis: Vec3 },
+  // Longitude and latitude around the object's origin, with the poles on
+  // the y axis.
+  Spherical,
+  // The angle around the y axis, and the height along it.
+  Cylindrical,
+  // Planar projections along each axis, blended by how much the normal
+  // faces that axis. Higher sharpness gives narrower blends.
+  Triplanar { sharpness: T },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct UvMapping {
+  pub projection: Projection,
+  pub transform: UvTransform,
+}
+
+impl UvMapping {
+  // Evaluates a texture with the given lookup, at the coordinates this
+  // mapping gives tc.
+  pub fn evaluate<F: Fn(&TexCoord) -> Color>(
+    &self,
+    tc: &TexCoord,
+    lookup: F,
+  ) -> Color {
+    let p = tc.object_p.0;
+    let project = |u: T, v: T, du: T, dv: T| {
+      self.transform.apply(&TexCoord {
+        u,
+        v,
+        du,
+        dv,
+        ..*tc
+      })
+    };
+    match self.projection {
+      Projection::Surface => lookup(&self.transform.apply(tc)),
+      Projection::Planar { u_axis, v_axis } => lookup(&project(
+        p.dot(u_axis),
+        p.dot(v_axis),
+        tc.footprint * u_axis.norm(),
+        tc.footprint * v_axis.norm(),
+      )),
+      Projection::Spherical => {
+        let r = p.norm().max(1e-6);
+        let d = p / r;
+        lookup(&project(
+          longitude(d),
+          (-d.y()).clamp(-1.0, 1.0).acos() / PI,
+          tc.footprint / (2.0 * PI * r),
+          tc.footprint / (PI * r),
+        ))
+      }
+      Projection::Cylindrical => {
+        let r = (p.x() * p.x() + p.z() * p.z()).sqrt().max(1e-6);
+        lookup(&project(
+          longitude(p),
+          p.y(),
+          tc.footprint / (2.0 * PI * r),
+          tc.footprint,
+        ))
+      }
+      Projection::Triplanar { sharpness } => {
+        let n = tc.normal.abs();
+        let w = Vec3::new(
+          n.x().powf(sharpness),
+          n.y().powf(sharpness),
+          n.z().powf(sharpness),
+        );
+        let w = w / (w.x() + w.y() + w.z());
+        let f = tc.footprint;
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        for &(weight, u, v) in &[
+          (w.x(), p.z(), p.y()),
+          (w.y(), p.x(), p.z()),
+          (w.z(), p.x(), p.y()),
+        ] {
+          if weight > 0.0 {
+            color += weight * lookup(&project(u, v, f, f));
+          }
+        }
+        color
+      }
+    }
+  }
+}
+
+// The angle of d around the y axis, in [0, 1], matching sphere surface
+// coordinates.
+fn longitude(d: Vec3) -> T {
+  ((-d.z()).atan2(d.x()) + PI) / (2.0 * PI)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn coordinates(p: Point, normal: Vec3) -> TexCoord {
+    TexCoord {
+      u: 0.0,
+      v: 0.0,
+      du: 0.0,
+      dv: 0.0,
+      p,
+      object_p: p,
+      normal,
+      footprint: 0.0,
+    }
+  }
+
+  fn uv(mapping: UvMapping, tc: &TexCoord) -> (T, T) {
+    let c = mapping.evaluate(tc, |tc| Color::new(tc.u, tc.v, 0.0));
+    (c.r(), c.g())
+  }
+
+  #[test]
+  fn test_uv_transform() {
+    let transform = UvTransform {
+      scale: (2.0, 3.0),
+      rotation: 0.5 * PI,
+      offset: (1.0, 0.0),
+    };
+    let mut tc =
+      coordinates(Point::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+    tc.u = 1.0;
+    tc.v = 1.0;
+    let tc = transform.apply(&tc);
+    assert!((tc.u - -2.0).abs() < 1e-5 && (tc.v - 2.0).abs() < 1e-5);
+  }
+
+  #[test]
+  fn test_projections() {
+    let mapping = |projection| UvMapping {
+      projection,
+      transform: UvTransform::identity(),
+    };
+    let tc = coordinates(Point::new(0.0, 0.5, -1.0), Vec3::new(0.0, 0.0, -1.0));
+    let planar = Projection::Planar {
+      u_axis: Vec3::new(0.0, 0.0, 2.0),
+      v_axis: Vec3::new(0.0, 1.0, 0.0),
+    };
+    assert_eq!(uv(mapping(planar), &tc), (-2.0, 0.5));
+    let (u, v) = uv(mapping(Projection::Cylindrical), &tc);
+    assert!((u - 0.75).abs() < 1e-6 && (v - 0.5).abs() < 1e-6);
+    // Facing straight down z, triplanar is the planar projection on xy.
+    let triplanar = Projection::Triplanar { sharpness: 4.0 };
+    assert_eq!(uv(mapping(triplanar), &tc), (0.0, 0.5));
+  }
+}
@@ -1,6 +1,11 @@
+use crate::microfacet::*;
+use crate::normal_map::*;
 use crate::object::*;
+use crate::principled::*;
 use crate::ray::*;
+use crate::spectrum::*;
 use crate::texture::*;
+use crate::thin_film::*;
 use crate::vec3::*;
 use rand::Rng;
 type T = f32;
@@ -10,29 +15,242 @@ pub struct ScatterResult {
   pub scattered_ray: Ray,
 }
 
+// An index of refraction, which may depend on the wavelength of light. That
+// dependence is what makes prisms and diamonds split white light into colors.
+// Wavelengths are in micrometers in the formulas below.
+#[derive(Clone, Copy)]
+pub enum RefractionIndex {
+  Constant(T),
+  // n = a + b / lambda^2.
+  Cauchy { a: T, b: T },
+  // n^2 = 1 + sum_i b_i lambda^2 / (lambda^2 - c_i).
+  Sellmeier { b: [T; 3], c: [T; 3] },
+}
+
+// The wavelength, in nanometers, of the sodium D line. Indices of refraction
+// are usually quoted at this wavelength, so RGB renders use it too.
+const SODIUM_D_LINE: T = 589.3;
+
+impl RefractionIndex {
+  // A dense flint glass, which disperses light much more than window glass.
+  pub fn flint_glass() -> RefractionIndex {
+    RefractionIndex::Cauchy { a: 1.728, b: 0.013_42 }
+  }
+  pub fn diamond() -> RefractionIndex {
+    RefractionIndex::Sellmeier {
+      b: [0.3306, 4.3356, 0.0],
+      c: [0.175 * 0.175, 0.106 * 0.106, 0.0],
+    }
+  }
+  // The index at the given wavelength in nanometers, or at the sodium D line
+  // if there is none.
+  pub fn at(&self, wavelength: Option<T>) -> T {
+    let lambda = wavelength.unwrap_or(SODIUM_D_LINE) / 1000.0;
+    let lambda2 = lambda * lambda;
+    match self {
+      RefractionIndex::Constant(n) => *n,
+      RefractionIndex::Cauchy { a, b } => a + b / lambda2,
+      RefractionIndex::Sellmeier { b, c } => {
+        let sum: T = (0..3).map(|i| b[i] * lambda2 / (lambda2 - c[i])).sum();
+        (1.0 + sum).sqrt()
+      }
+    }
+  }
+  pub fn is_dispersive(&self) -> bool {
+    !matches!(self, RefractionIndex::Constant(_))
+  }
+}
+
+// How an alpha texture decides whether a hit is cut out.
+#[derive(Clone, Copy, Debug)]
+pub enum AlphaMode {
+  // Hits where alpha is below the threshold pass through.
+  Threshold(T),
+  // Hits pass through with probability 1 - alpha, so partially transparent
+  // texels average out to the right coverage.
+  Stochastic,
+}
+
 #[derive(Clone)]
 pub enum Material {
   Lambertian { albedo: Texture },
   Metal { albedo: Texture, fuzz: T },
-  Dielectric { refraction_index: T },
+  // absorption is the Beer-Lambert absorption coefficient per unit length,
+  // for each channel. Zero absorption gives clear glass.
+  Dielectric {
+    refraction_index: RefractionIndex,
+    absorption: Color,
+    thin_film: Option<ThinFilm>,
+  },
+  // A rough metal, with a GGX microfacet distribution. albedo is the
+  // reflectance at normal incidence, and roughness is perceptual roughness in
+  // [0, 1]. anisotropy is in [-1, 1], and stretches the highlight along dpdu
+  // for positive values and across it for negative ones.
+  Conductor {
+    albedo: Texture,
+    roughness: Texture,
+    anisotropy: T,
+    thin_film: Option<ThinFilm>,
+  },
+  // A rough glass-like material, with a GGX microfacet distribution.
+  RoughDielectric {
+    refraction_index: T,
+    absorption: Color,
+    roughness: Texture,
+    anisotropy: T,
+  },
+  Principled(Box<Principled>),
+  // Another material, shaded about a normal perturbed by a normal or bump
+  // map.
+  NormalMapped {
+    material: Box<Material>,
+    normal_map: NormalMap,
+  },
+  // Another material, with holes where its alpha texture says so. Rays pass
+  // through the holes as if nothing was there.
+  Cutout {
+    material: Box<Material>,
+    alpha: Texture,
+    mode: AlphaMode,
+  },
 }
 
 impl Material {
+  // Scatters a ray off the surface, treating it as at least min_roughness
+  // rough. Raising that above 0 after the first bounce blurs the caustics
+  // that smooth glass and metal focus, which would otherwise be fireflies.
   pub fn scatter(
     &self,
     incident_ray: &Ray,
     hit: &HitResultPayload,
+    min_roughness: T,
   ) -> Option<ScatterResult> {
     match self {
       Material::Lambertian { albedo: a } => {
         scatter_lambertian(a, incident_ray, hit)
       }
       Material::Metal { albedo: a, fuzz: f } => {
-        scatter_metal(a, *f, incident_ray, hit)
+        let f = f.max(fuzz_from_roughness(min_roughness));
+        scatter_metal(a, f, incident_ray, hit)
+      }
+      Material::Dielectric {
+        refraction_index: ir,
+        absorption: ab,
+        thin_film: tf,
+      } => scatter_dielectric(
+        ir.at(incident_ray.wavelength),
+        *ab,
+        tf.as_ref(),
+        min_roughness,
+        incident_ray,
+        hit,
+      ),
+      Material::Conductor {
+        albedo: a,
+        roughness: r,
+        anisotropy: an,
+        thin_film: tf,
+      } => {
+        let r = r.scalar_sample(hit).max(min_roughness);
+        scatter_conductor(a, r, *an, tf.as_ref(), incident_ray, hit)
+      }
+      Material::RoughDielectric {
+        refraction_index: ir,
+        absorption: ab,
+        roughness: r,
+        anisotropy: an,
+      } => {
+        let r = r.scalar_sample(hit).max(min_roughness);
+        scatter_rough_dielectric(*ir, *ab, r, *an, incident_ray, hit)
+      }
+      Material::Principled(params) => {
+        scatter_principled(params, min_roughness, incident_ray, hit)
+      }
+      Material::NormalMapped {
+        material,
+        normal_map,
+      } => {
+        let normal = facing_normal(normal_map.shading_normal(hit), incident_ray);
+        let hit = HitResultPayload { normal, ..*hit };
+        material.scatter(incident_ray, &hit, min_roughness)
       }
+      Material::Cutout { material, .. } => {
+        material.scatter(incident_ray, hit, min_roughness)
+      }
+    }
+  }
+  // Whether this material has an alpha cutout, so objects need to check
+  // is_cut_out for their hits.
+  pub fn has_cutout(&self) -> bool {
+    match self {
+      Material::Cutout { .. } => true,
+      Material::NormalMapped { material, .. } => material.has_cutout(),
+      _ => false,
+    }
+  }
+  // Whether a hit falls in a hole of this material's alpha cutout.
+  pub fn is_cut_out(&self, hit: &HitResultPayload) -> bool {
+    match self {
+      Material::Cutout {
+        material,
+        alpha,
+        mode,
+      } => {
+        let alpha = alpha.scalar_sample(hit);
+        let cut = match mode {
+          AlphaMode::Threshold(threshold) => alpha < *threshold,
+          AlphaMode::Stochastic => rand::thread_rng().gen::<T>() >= alpha,
+        };
+        cut || material.is_cut_out(hit)
+      }
+      Material::NormalMapped { material, .. } => material.is_cut_out(hit),
+      _ => false,
+    }
+  }
+  // Whether scattering off this material depends on the wavelength of light,
+  // so that a spectral render must not reuse the path for other wavelengths.
+  pub fn is_dispersive(&self) -> bool {
+    match self {
       Material::Dielectric {
         refraction_index: ir,
-      } => scatter_dielectric(*ir, incident_ray, hit),
+        thin_film: tf,
+        ..
+      } => ir.is_dispersive() || tf.is_some(),
+      Material::Conductor { thin_film: tf, .. } => tf.is_some(),
+      Material::NormalMapped { material, .. }
+      | Material::Cutout { material, .. } => material.is_dispersive(),
+      _ => false,
+    }
+  }
+  // The color the surface reflects or transmits at a hit, for albedo
+  // passes. Clear glass is white.
+  pub fn albedo(&self, hit: &HitResultPayload) -> Color {
+    match self {
+      Material::Lambertian { albedo }
+      | Material::Metal { albedo, .. }
+      | Material::Conductor { albedo, .. } => albedo.sample(hit),
+      Material::Dielectric { .. } | Material::RoughDielectric { .. } => {
+        Color::new(1.0, 1.0, 1.0)
+      }
+      Material::Principled(params) => params.base_color.sample(hit),
+      Material::NormalMapped { material, .. }
+      | Material::Cutout { material, .. } => material.albedo(hit),
+    }
+  }
+  // The normal the material shades a hit with, which normal maps perturb.
+  pub fn shading_normal(
+    &self,
+    incident_ray: &Ray,
+    hit: &HitResultPayload,
+  ) -> Vec3 {
+    match self {
+      Material::NormalMapped { normal_map, .. } => {
+        facing_normal(normal_map.shading_normal(hit), incident_ray)
+      }
+      Material::Cutout { material, .. } => {
+        material.shading_normal(incident_ray, hit)
+      }
+      _ => hit.normal,
     }
   }
   pub fn new_lambertian(albedo: Texture) -> Material {
@@ -45,7 +263,119 @@ impl Material {
     }
   }
   pub fn new_dielectric(refraction_index: T) -> Material {
-    Material::Dielectric { refraction_index }
+    Material::new_absorbing_dielectric(
+      refraction_index,
+      Color::new(0.0, 0.0, 0.0),
+    )
+  }
+  pub fn new_absorbing_dielectric(
+    refraction_index: T,
+    absorption: Color,
+  ) -> Material {
+    Material::new_dispersive_dielectric(
+      RefractionIndex::Constant(refraction_index),
+      absorption,
+    )
+  }
+  pub fn new_dispersive_dielectric(
+    refraction_index: RefractionIndex,
+    absorption: Color,
+  ) -> Material {
+    Material::Dielectric {
+      refraction_index,
+      absorption,
+      thin_film: None,
+    }
+  }
+  // A colored glass that lets through the given fraction of each channel
+  // after light travels the given distance inside it. The fractions are
+  // clamped to (0, 1], as glass can't add light, and the distance must be
+  // positive.
+  pub fn new_colored_dielectric(
+    refraction_index: T,
+    transmittance: Color,
+    distance: T,
+  ) -> Result<Material, String> {
+    if !(distance > 0.0 && distance.is_finite()) {
+      return Err(format!("Bad colored glass distance: {}", distance));
+    }
+    let absorption = |t: T| -t.clamp(1e-6, 1.0).ln() / distance;
+    Ok(Material::new_absorbing_dielectric(
+      refraction_index,
+      Color::new(
+        absorption(transmittance.r()),
+        absorption(transmittance.g()),
+        absorption(transmittance.b()),
+      ),
+    ))
+  }
+  pub fn new_conductor(
+    albedo: Texture,
+    roughness: Texture,
+    anisotropy: T,
+  ) -> Material {
+    Material::Conductor {
+      albedo,
+      roughness,
+      anisotropy,
+      thin_film: None,
+    }
+  }
+  pub fn new_rough_dielectric(
+    refraction_index: T,
+    absorption: Color,
+    roughness: Texture,
+    anisotropy: T,
+  ) -> Material {
+    Material::RoughDielectric {
+      refraction_index,
+      absorption,
+      roughness,
+      anisotropy,
+    }
+  }
+  pub fn new_principled(params: Principled) -> Material {
+    Material::Principled(Box::new(params))
+  }
+  // Coats a dielectric or a conductor with an iridescent thin film.
+  pub fn with_thin_film(mut self, film: ThinFilm) -> Material {
+    match self {
+      Material::Dielectric {
+        ref mut thin_film, ..
+      }
+      | Material::Conductor {
+        ref mut thin_film, ..
+      } => *thin_film = Some(film),
+      _ => panic!("Only dielectrics and conductors can have a thin film."),
+    }
+    self
+  }
+  // Shades this material about normals perturbed by the given map.
+  pub fn with_normal_map(self, normal_map: NormalMap) -> Material {
+    Material::NormalMapped {
+      material: Box::new(self),
+      normal_map,
+    }
+  }
+  // Cuts holes in this material where alpha, read as a scalar, is low.
+  pub fn with_cutout(self, alpha: Texture, mode: AlphaMode) -> Material {
+    Material::Cutout {
+      material: Box::new(self),
+      alpha,
+      mode,
+    }
+  }
+}
+
+// A perturbed normal can face away from the incident ray, which would send
+// light through the surface. Bends such normals back just far enough.
+fn facing_normal(normal: Vec3, incident_ray: &Ray) -> Vec3 {
+  let wo = -incident_ray.direction.normalize();
+  let cos_theta = normal.dot(wo);
+  if cos_theta >= 0.01 {
+    normal
+  } else {
+    (normal + (0.01 - cos_theta) * wo).normalize()
   }
 }
 
@@ -59,20 +389,16 @@ fn scatter_lambertian(
     scatter_direction = hit.normal;
   }
   Some(ScatterResult {
-    attenuation: albedo.value(hit.u, hit.v, hit.p),
-    scattered_ray: Ray {
-      origin: hit.p,
-      direction: scatter_direction,
-      time: incident_ray.time,
-    },
+    attenuation: albedo.sample(hit),
+    scattered_ray: incident_ray.scatter(hit, scatter_direction),
   })
 }
 
-fn reflect(v: Vec3, n: Vec3) -> Vec3 {
+pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
   v - 2.0 * v.dot(n) * n
 }
 
-fn refract(v: Vec3, n: Vec3, refraction_ratio: T) -> Vec3 {
+pub fn refract(v: Vec3, n: Vec3, refraction_ratio: T) -> Vec3 {
   let cos_theta = (-v).dot(n).min(1.0);
   let r_out_perp = refraction_ratio * (v + cos_theta * n);
   let r_out_parallel =
@@ -80,6 +406,14 @@ fn refract(v: Vec3, n: Vec3, refraction_ratio: T) -> Vec3 {
   r_out_perp + r_out_parallel
 }
 
+// The fuzz that blurs a metal's reflections about as much as GGX roughness
+// does. Fuzz offsets a reflection by a random vector of up to its own length,
+// turning it by about that many radians, while GGX spreads reflections over
+// about twice its alpha, the roughness squared.
+fn fuzz_from_roughness(roughness: T) -> T {
+  (2.0 * roughness * roughness).min(1.0)
+}
+
 fn scatter_metal(
   albedo: &Texture,
   fuzz: T,
@@ -88,32 +422,50 @@ fn scatter_metal(
 ) -> Option<ScatterResult> {
   let r = incident_ray.direction;
   let reflected = reflect(r.normalize(), hit.normal);
-  let scattered = Ray {
-    origin: hit.p,
-    direction: reflected + fuzz * Vec3::random_unit(),
-    time: incident_ray.time,
-  };
+  let scattered =
+    incident_ray.scatter(hit, reflected + fuzz * Vec3::random_unit());
   if reflected.dot(hit.normal) > 0.0 {
     return Some(ScatterResult {
-      attenuation: albedo.value(hit.u, hit.v, hit.p),
+      attenuation: albedo.sample(hit),
       scattered_ray: scattered,
     });
   }
-  return None;
+  None
 }
 
 fn scatter_dielectric(
   refraction_index: T,
+  absorption: Color,
+  thin_film: Option<&ThinFilm>,
+  roughness: T,
   incident_ray: &Ray,
   hit: &HitResultPayload,
 ) -> Option<ScatterResult> {
-  let attenuation = Color::new(1.0, 1.0, 1.0);
+  let mut attenuation = unentered_transmittance(absorption, incident_ray, hit);
   let refraction_ratio = if hit.front_face {
     1.0 / refraction_index
   } else {
     refraction_index
   };
 
+  // Roughened glass scatters as in scatter_rough_dielectric. Thin films
+  // stay smooth, since their colors need a flat surface.
+  if roughness > 0.0 && thin_film.is_none() {
+    let frame = Frame::from_normal(hit.normal);
+    let wo = frame.to_local(-incident_ray.direction.normalize());
+    if wo.z() <= 0.0 {
+      return None;
+    }
+    let distribution = TrowbridgeReitz::from_roughness(roughness, 0.0);
+    let (wi, g) =
+      sample_rough_dielectric(&distribution, wo, 1.0 / refraction_ratio)?;
+    let direction = frame.to_world(wi);
+    return Some(ScatterResult {
+      attenuation: g * attenuation,
+      scattered_ray: incident_ray.scatter_through(hit, direction, absorption),
+    });
+  }
+
   let r = incident_ray.direction;
   let unit_direction = r / r.norm();
 
@@ -126,20 +478,322 @@ fn scatter_dielectric(
     let r0 = (1.0 - refraction_ratio) / (1.0 + refraction_ratio).powf(2.0);
     r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
   };
-  let direction =
-    if cannot_refract || reflectance(cos_theta, refraction_ratio) > rng.gen() {
+  let direction = if cannot_refract {
+    reflect(unit_direction, hit.normal)
+  } else if let Some(film) = thin_film {
+    // The film's reflectance differs per channel, so reflect with their
+    // average probability and reweight each channel.
+    let (n1, n3) = if hit.front_face {
+      (1.0, refraction_index)
+    } else {
+      (refraction_index, 1.0)
+    };
+    let r = match incident_ray.wavelength {
+      Some(lambda) => {
+        let r = film.reflectance(cos_theta, n1, n3, lambda);
+        Color::new(r, r, r)
+      }
+      None => film.rgb_reflectance(cos_theta, n1, [n3, n3, n3]),
+    };
+    let white = Color::new(1.0, 1.0, 1.0);
+    let p = ((r.r() + r.g() + r.b()) / 3.0).clamp(1e-4, 1.0 - 1e-4);
+    if p > rng.gen() {
+      attenuation = attenuation * ((1.0 / p) * r);
       reflect(unit_direction, hit.normal)
     } else {
+      attenuation = attenuation * ((1.0 / (1.0 - p)) * (white - r));
       refract(unit_direction, hit.normal, refraction_ratio)
-    };
+    }
+  } else if reflectance(cos_theta, refraction_ratio) > rng.gen() {
+    reflect(unit_direction, hit.normal)
+  } else {
+    refract(unit_direction, hit.normal, refraction_ratio)
+  };
+
+  Some(ScatterResult {
+    attenuation,
+    scattered_ray: incident_ray.scatter_through(hit, direction, absorption),
+  })
+}
+
+// Light is absorbed along the way as rays travel through their media, but a
+// ray leaving a medium it was never seen entering, such as one from a camera
+// inside glass, traveled inside it all the way from its origin.
+pub fn unentered_transmittance(
+  absorption: Color,
+  incident_ray: &Ray,
+  hit: &HitResultPayload,
+) -> Color {
+  if hit.front_face || !incident_ray.media.is_empty() {
+    return Color::new(1.0, 1.0, 1.0);
+  }
+  transmittance(absorption, (hit.p - incident_ray.origin).norm())
+}
 
-  let scattered = Ray {
-    origin: hit.p,
-    direction: direction,
-    time: incident_ray.time,
+// Reflects off a GGX conductor, choosing the microfacet normal from the
+// distribution of visible normals. With that sampling strategy the weight of
+// the sample is F * G2 / G1, which is at most F, so the material never creates
+// energy the way fuzzed reflection can.
+fn scatter_conductor(
+  albedo: &Texture,
+  roughness: T,
+  anisotropy: T,
+  thin_film: Option<&ThinFilm>,
+  incident_ray: &Ray,
+  hit: &HitResultPayload,
+) -> Option<ScatterResult> {
+  let frame = Frame::from_normal_and_tangent(hit.normal, hit.dpdu);
+  let wo = frame.to_local(-incident_ray.direction.normalize());
+  if wo.z() <= 0.0 {
+    return None;
+  }
+  let distribution = TrowbridgeReitz::from_roughness(roughness, anisotropy);
+  let mut rng = rand::thread_rng();
+  let wm = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+  let wi = reflect(-wo, wm);
+  if wi.z() <= 0.0 {
+    return None;
+  }
+  let f0 = albedo.sample(hit);
+  let f = match thin_film {
+    None => fresnel_schlick(wo.dot(wm), f0),
+    Some(film) => coated_conductor_fresnel(film, wo.dot(wm), f0, incident_ray),
   };
   Some(ScatterResult {
-    attenuation: attenuation,
-    scattered_ray: scattered,
+    attenuation: (distribution.g(wo, wi) / distribution.g1(wo)) * f,
+    scattered_ray: incident_ray.scatter(hit, frame.to_world(wi)),
   })
 }
+
+// The Fresnel reflectance of a conductor under a thin film. In a spectral
+// render this is only valid for the ray's hero wavelength.
+fn coated_conductor_fresnel(
+  film: &ThinFilm,
+  cos_theta: T,
+  f0: Color,
+  incident_ray: &Ray,
+) -> Color {
+  match incident_ray.wavelength {
+    Some(lambda) => {
+      let n3 = refraction_index_from_reflectance(lift_rgb(f0, lambda));
+      let r = film.reflectance(cos_theta, 1.0, n3, lambda);
+      Color::new(r, r, r)
+    }
+    None => film.rgb_reflectance(
+      cos_theta,
+      1.0,
+      [
+        refraction_index_from_reflectance(f0.r()),
+        refraction_index_from_reflectance(f0.g()),
+        refraction_index_from_reflectance(f0.b()),
+      ],
+    ),
+  }
+}
+
+// Like scatter_dielectric, but refracting and reflecting about a microfacet
+// normal sampled as in scatter_conductor. Choosing between reflection and
+// refraction with probability given by the Fresnel term cancels it out of the
+// weight, leaving G2 / G1.
+fn scatter_rough_dielectric(
+  refraction_index: T,
+  absorption: Color,
+  roughness: T,
+  anisotropy: T,
+  incident_ray: &Ray,
+  hit: &HitResultPayload,
+) -> Option<ScatterResult> {
+  // The ratio of the index on the far side of the surface to the near side.
+  let eta = if hit.front_face {
+    refraction_index
+  } else {
+    1.0 / refraction_index
+  };
+  let frame = Frame::from_normal_and_tangent(hit.normal, hit.dpdu);
+  let wo = frame.to_local(-incident_ray.direction.normalize());
+  if wo.z() <= 0.0 {
+    return None;
+  }
+  let distribution = TrowbridgeReitz::from_roughness(roughness, anisotropy);
+  let (wi, g) = sample_rough_dielectric(&distribution, wo, eta)?;
+  let direction = frame.to_world(wi);
+  Some(ScatterResult {
+    attenuation: g * unentered_transmittance(absorption, incident_ray, hit),
+    scattered_ray: incident_ray.scatter_through(hit, direction, absorption),
+  })
+}
+
+// Samples an incident direction for a rough dielectric interface, in the local
+// shading frame, returning it with its weight. eta is the ratio of the index
+// on the far side of the surface to the one on the side of wo.
+pub fn sample_rough_dielectric(
+  distribution: &TrowbridgeReitz,
+  wo: Vec3,
+  eta: T,
+) -> Option<(Vec3, T)> {
+  let mut rng = rand::thread_rng();
+  let wm = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+  let wi = if fresnel_dielectric(wo.dot(wm), eta) > rng.gen() {
+    let wi = reflect(-wo, wm);
+    if wi.z() <= 0.0 {
+      return None;
+    }
+    wi
+  } else {
+    let wi = refract(-wo, wm, 1.0 / eta);
+    if wi.z() >= 0.0 {
+      return None;
+    }
+    wi
+  };
+  Some((wi, distribution.g(wo, wi) / distribution.g1(wo)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ray_down(x: T, y: T) -> Ray {
+    Ray {
+      origin: Point::new(x, y, 1.0),
+      direction: Vec3::new(0.0, 0.0, -1.0),
+      time: 0.0,
+      wavelength: None,
+      cone: RayCone {
+        width: 0.0,
+        spread: 0.0,
+      },
+      media: Media::none(),
+    }
+  }
+
+  #[test]
+  fn test_colored_glass_transmittance() {
+    let tint = Color::new(0.2, 0.5, 1.0);
+    let glass = Material::new_colored_dielectric(1.5, tint, 0.4).unwrap();
+    // A ray from the center of a ball of the glass, as from a camera inside
+    // it, reaches its surface with the transmittance left, as the ball's
+    // radius is the distance.
+    let ball = Sphere::new(Point::new(0.0, 0.0, 0.0), 0.4, glass);
+    let ray = Ray {
+      origin: Point::new(0.0, 0.0, 0.0),
+      ..ray_down(0.0, 0.0)
+    };
+    let hit = ball.hit(0.001, 10.0, &ray).unwrap();
+    let payload = ball.hit_payload(hit.t, &ray);
+    assert!(!payload.front_face);
+    let sr = payload.material.scatter(&ray, &payload, 0.0).unwrap();
+    for (a, t) in [
+      (sr.attenuation.r(), tint.r()),
+      (sr.attenuation.g(), tint.g()),
+      (sr.attenuation.b(), tint.b()),
+    ] {
+      assert!((a - t).abs() < 1e-4, "{} != {}", a, t);
+    }
+    // Transmittance above 1 is clamped, rather than adding light.
+    let bright = Color::new(2.0, 1.0, 1.0);
+    match Material::new_colored_dielectric(1.5, bright, 1.0) {
+      Ok(Material::Dielectric { absorption, .. }) => {
+        assert!(absorption.r() >= 0.0 && absorption.r().is_finite())
+      }
+      _ => unreachable!(),
+    }
+    assert!(Material::new_colored_dielectric(1.5, tint, 0.0).is_err());
+  }
+
+  // The light left after a ray's path through the balls, attenuated by their
+  // materials and by the media in between as in the renderer.
+  fn transmitted(balls: &[Sphere], mut ray: Ray) -> Color {
+    let mut light = Color::new(1.0, 1.0, 1.0);
+    loop {
+      let nearest = balls
+        .iter()
+        .filter_map(|ball| Some((ball.hit(0.001, 10.0, &ray)?.t, ball)))
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+      let (t, ball) = match nearest {
+        Some(nearest) => nearest,
+        None => return light,
+      };
+      let payload = ball.hit_payload(t, &ray);
+      let sr = payload.material.scatter(&ray, &payload, 0.0).unwrap();
+      let absorbed = ray.media.transmittance(t * ray.direction.norm());
+      light = light * absorbed * sr.attenuation;
+      ray = sr.scattered_ray;
+    }
+  }
+
+  #[test]
+  fn test_absorption_around_nested_object() {
+    // Straight through a ball of colored glass with a clear ball in its
+    // middle, light only travels through the colored glass for the length of
+    // the outer diameter less the inner one. An index of 1 keeps the balls
+    // from reflecting any of it.
+    let tint = Color::new(0.2, 0.5, 1.0);
+    let colored = Material::new_colored_dielectric(1.0, tint, 0.8).unwrap();
+    let absorption = match colored {
+      Material::Dielectric { absorption, .. } => absorption,
+      _ => unreachable!(),
+    };
+    let rough = Material::new_rough_dielectric(
+      1.0,
+      absorption,
+      Texture::constant(0.0),
+      0.0,
+    );
+    let principled = Material::new_principled(Principled {
+      roughness: Texture::constant(0.0),
+      specular: Texture::constant(0.0),
+      transmission: Texture::constant(1.0),
+      absorption,
+      ..Principled::new(Texture::constant(1.0))
+    });
+    for glass in [colored, rough, principled] {
+      let center = Point::new(0.0, 0.0, 0.0);
+      let balls = [
+        Sphere::new(center, 0.8, glass),
+        Sphere::new(center, 0.4, Material::new_dielectric(1.0)),
+      ];
+      let light = transmitted(&balls, ray_down(0.0, 0.0));
+      for (a, t) in [
+        (light.r(), tint.r()),
+        (light.g(), tint.g()),
+        (light.b(), tint.b()),
+      ] {
+        assert!((a - t).abs() < 1e-3, "{} != {}", a, t);
+      }
+    }
+  }
+
+  #[test]
+  fn test_anisotropy_follows_dpdu() {
+    let material = Material::new_conductor(
+      Texture::Color(Color::new(0.9, 0.9, 0.9)),
+      Texture::Color(Color::new(0.5, 0.5, 0.5)),
+      0.9,
+    );
+    // The top of a ball, where u runs along x.
+    let ball = Sphere::new(Point::new(0.0, 0.0, 0.0), 0.5, material);
+    let ray = ray_down(0.0, 0.0);
+    let hit = ball.hit(0.001, 10.0, &ray).unwrap();
+    let mut payload = ball.hit_payload(hit.t, &ray);
+    // How far reflections stray along x and along y, with u along x and
+    // then turned a quarter turn to run along y.
+    let mut spreads = Vec::new();
+    for dpdu in [payload.dpdu, Vec3::new(0.0, 1.0, 0.0)] {
+      payload.dpdu = dpdu;
+      let (mut along_x, mut along_y) = (0.0, 0.0);
+      for _ in 0..2000 {
+        if let Some(sr) = payload.material.scatter(&ray, &payload, 0.0) {
+          let d = sr.scattered_ray.direction.normalize();
+          along_x += d.x().abs();
+          along_y += d.y().abs();
+        }
+      }
+      spreads.push((along_x, along_y));
+    }
+    // The highlight stretches along u, so it turns with the
+    // parameterization.
+    assert!(spreads[0].0 > 1.5 * spreads[0].1);
+    assert!(spreads[1].1 > 1.5 * spreads[1].0);
+  }
+}
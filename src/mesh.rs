@@ -0,0 +1,249 @@
+// Triangle meshes, whose vertices can move while the shutter is open, for
+// deformation blur.
+use crate::aabb::*;
+use crate::material2::*;
+use crate::object::*;
+use crate::ray::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+type T = f32;
+
+pub struct Mesh {
+  // Every vertex's position at each motion step, with the steps spread
+  // evenly from time0 to time1. Vertices move linearly between steps, and
+  // hold still before the first and after the last.
+  steps: Vec<Vec<Point>>,
+  time0: T,
+  time1: T,
+  uvs: Vec<(T, T)>,
+  // Each triangle's vertices, counterclockwise seen from its front.
+  triangles: Vec<[usize; 3]>,
+  material: Arc<Material>,
+}
+
+impl Mesh {
+  // A mesh that holds still.
+  pub fn new(
+    positions: Vec<Point>,
+    uvs: Vec<(T, T)>,
+    triangles: Vec<[usize; 3]>,
+    material: impl Into<Arc<Material>>,
+  ) -> Mesh {
+    assert_eq!(positions.len(), uvs.len());
+    assert!(triangles.iter().flatten().all(|&i| i < positions.len()));
+    Mesh {
+      steps: vec![positions],
+      time0: 0.0,
+      time1: 1.0,
+      uvs,
+      triangles,
+      material: material.into(),
+    }
+  }
+
+  // Replaces the vertices' positions with ones that move from time0 to
+  // time1, through each of steps in turn.
+  pub fn with_motion(
+    mut self,
+    steps: Vec<Vec<Point>>,
+    time0: T,
+    time1: T,
+  ) -> Mesh {
+    assert!(!steps.is_empty() && time1 > time0);
+    assert!(steps.iter().all(|step| step.len() == self.uvs.len()));
+    self.steps = steps;
+    self.time0 = time0;
+    self.time1 = time1;
+    self
+  }
+
+  // The mesh's triangles, as objects to add to a world.
+  pub fn into_triangles(self) -> Vec<Box<dyn Object + Send + Sync>> {
+    let mesh = Arc::new(self);
+    (0..mesh.triangles.len())
+      .map(|index| {
+        Box::new(Triangle {
+          mesh: mesh.clone(),
+          index,
+        }) as Box<dyn Object + Send + Sync>
+      })
+      .collect()
+  }
+
+  // Where the motion step before time is, and how far it is to the next.
+  fn step(&self, time: T) -> (usize, T) {
+    let last = self.steps.len() - 1;
+    if last == 0 {
+      return (0, 0.0);
+    }
+    let x = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0)
+      * last as T;
+    let i = (x.floor() as usize).min(last - 1);
+    (i, x - i as T)
+  }
+}
+
+struct Triangle {
+  mesh: Arc<Mesh>,
+  index: usize,
+}
+
+impl Triangle {
+  fn vertices(&self, time: T) -> [Point; 3] {
+    let mesh = &self.mesh;
+    let (i, s) = mesh.step(time);
+    let next = (i + 1).min(mesh.steps.len() - 1);
+    mesh.triangles[self.index].map(|v| {
+      Point(mesh.steps[i][v].0 * (1.0 - s) + mesh.steps[next][v].0 * s)
+    })
+  }
+
+  // The barycentric weights of a point on the triangle's plane.
+  fn barycentrics(p: Point, [a, b, c]: [Point; 3]) -> (T, T, T) {
+    let (e1, e2, ep) = (b - a, c - a, p - a);
+    let (d11, d12, d22) = (e1.dot(e1), e1.dot(e2), e2.dot(e2));
+    let (dp1, dp2) = (ep.dot(e1), ep.dot(e2));
+    let denominator = d11 * d22 - d12 * d12;
+    let w1 = (d22 * dp1 - d12 * dp2) / denominator;
+    let w2 = (d11 * dp2 - d12 * dp1) / denominator;
+    (1.0 - w1 - w2, w1, w2)
+  }
+}
+
+impl Object for Triangle {
+  // Moller and Trumbore's intersection test.
+  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult<'_>> {
+    let [a, b, c] = self.vertices(ray.time);
+    let (e1, e2) = (b - a, c - a);
+    let pvec = ray.direction.cross(e2);
+    let determinant = e1.dot(pvec);
+    if determinant.abs() < 1e-12 {
+      return None;
+    }
+    let inverse = 1.0 / determinant;
+    let tvec = ray.origin - a;
+    let u = tvec.dot(pvec) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+      return None;
+    }
+    let qvec = tvec.cross(e1);
+    let v = ray.direction.dot(qvec) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+      return None;
+    }
+    let t = e2.dot(qvec) * inverse;
+    if t < t_min || t > t_max || is_cut_out(self, &self.mesh.material, t, ray) {
+      return None;
+    }
+    Some(HitResult::new(t, self))
+  }
+
+  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload<'_> {
+    let vertices = self.vertices(ray.time);
+    let [a, b, c] = vertices;
+    let point = ray.at(t);
+    let (w0, w1, w2) = Triangle::barycentrics(point, vertices);
+    let [uv0, uv1, uv2] =
+      self.mesh.triangles[self.index].map(|v| self.mesh.uvs[v]);
+    let u = w0 * uv0.0 + w1 * uv1.0 + w2 * uv2.0;
+    let v = w0 * uv0.1 + w1 * uv1.1 + w2 * uv2.1;
+    let normal = (b - a).cross(c - a).normalize();
+    // Solve dp = dp/du du + dp/dv dv along two edges, as in pbrt.
+    let (du02, dv02) = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+    let (du12, dv12) = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+    let (dp02, dp12) = (a - c, b - c);
+    let determinant = du02 * dv12 - dv02 * du12;
+    let payload =
+      HitResultPayload::new(point, ray, normal, &self.mesh.material, u, v);
+    if determinant.abs() < 1e-12 {
+      return payload;
+    }
+    payload.with_derivatives(
+      (dv12 * dp02 - dv02 * dp12) / determinant,
+      (du02 * dp12 - du12 * dp02) / determinant,
+    )
+  }
+
+  // Vertices move linearly between steps, so the triangle stays within the
+  // box around where it is at time0, time1 and each step in between.
+  fn bounding_box(&self, time0: T, time1: T) -> Option<BoundingBox> {
+    let mesh = &self.mesh;
+    let triangle = mesh.triangles[self.index];
+    let mut points = Vec::new();
+    points.extend_from_slice(&self.vertices(time0));
+    points.extend_from_slice(&self.vertices(time1));
+    let last = mesh.steps.len().max(2) - 1;
+    for (i, step) in mesh.steps.iter().enumerate() {
+      let time = mesh.time0 + (mesh.time1 - mesh.time0) * i as T / last as T;
+      if time > time0 && time < time1 {
+        points.extend(triangle.iter().map(|&v| step[v]));
+      }
+    }
+    // Pad the box, so flat triangles have some thickness.
+    BoundingBox::around(points).map(|bb| bb.padded(1e-4))
+  }
+
+  fn material(&self) -> Option<&Material> {
+    Some(&self.mesh.material)
+  }
+
+  // The point with the same barycentric weights.
+  fn position_at(&self, t: T, ray: &Ray, time: T) -> Point {
+    let (w0, w1, w2) =
+      Triangle::barycentrics(ray.at(t), self.vertices(ray.time));
+    let [a, b, c] = self.vertices(time);
+    Point(w0 * a.0 + w1 * b.0 + w2 * c.0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::texture::*;
+
+  fn ray(time: T) -> Ray {
+    Ray {
+      origin: Point::new(0.25, 0.25, 1.0),
+      direction: Vec3::new(0.0, 0.0, -1.0),
+      time,
+      wavelength: None,
+      cone: RayCone {
+        width: 0.0,
+        spread: 0.0,
+      },
+      media: Media::none(),
+    }
+  }
+
+  #[test]
+  fn test_deforming_triangle() {
+    let positions = |z: T| {
+      vec![
+        Point::new(0.0, 0.0, z),
+        Point::new(1.0, 0.0, z),
+        Point::new(0.0, 1.0, z),
+      ]
+    };
+    let mesh = Mesh::new(
+      positions(0.0),
+      vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+      vec![[0, 1, 2]],
+      Material::new_lambertian(Texture::constant(0.5)),
+    )
+    .with_motion(vec![positions(0.0), positions(-1.0)], 0.0, 1.0);
+    let triangles = mesh.into_triangles();
+    let triangle = &triangles[0];
+    // Halfway through, the triangle is halfway down.
+    let hit = triangle.hit(0.0, 10.0, &ray(0.5)).unwrap();
+    assert!((hit.t - 1.5).abs() < 1e-5);
+    let payload = triangle.hit_payload(hit.t, &ray(0.5));
+    assert!((payload.u - 0.25).abs() < 1e-5 && (payload.v - 0.25).abs() < 1e-5);
+    assert!((payload.dpdu - Vec3::new(1.0, 0.0, 0.0)).norm() < 1e-5);
+    // The box holds it all the way down.
+    let bb = triangle.bounding_box(0.0, 1.0).unwrap();
+    assert!(bb.hit(0.0, 10.0, &ray(0.0)));
+    let corners = bb.corners();
+    assert!(corners.iter().any(|c| c.0.z() < -0.99));
+  }
+}
@@ -0,0 +1,228 @@
+// Trowbridge-Reitz (GGX) microfacet distribution, shared by the rough
+// materials in material2.rs.
+//
+// Everything in here works in a local shading frame, where the surface normal
+// is +z and the two tangents are +x and +y. See Frame for moving vectors in
+// and out of that frame.
+use crate::vec3::*;
+use std::f32::consts::PI;
+
+type T = f32;
+
+// An orthonormal basis (s, t, n) around a shading normal n.
+#[derive(Clone, Copy)]
+pub struct Frame {
+  s: Vec3,
+  t: Vec3,
+  n: Vec3,
+}
+
+impl Frame {
+  // Builds a frame around a unit normal, picking an arbitrary tangent. This
+  // is the branchless construction from Duff et al., "Building an Orthonormal
+  // Basis, Revisited" (2017).
+  pub fn from_normal(n: Vec3) -> Frame {
+    let sign = 1.0_f32.copysign(n.z());
+    let a = -1.0 / (sign + n.z());
+    let b = n.x() * n.y() * a;
+    let s = Vec3::new(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x());
+    let t = Vec3::new(b, sign + n.y() * n.y() * a, -n.y());
+    Frame { s, t, n }
+  }
+
+  // Builds a frame around a unit normal whose first tangent follows the
+  // given direction, such as dpdu, so that anisotropic materials line up
+  // with the surface's parameterization. Tangents that are zero or along
+  // the normal give from_normal's arbitrary one.
+  pub fn from_normal_and_tangent(n: Vec3, tangent: Vec3) -> Frame {
+    let s = tangent - tangent.dot(n) * n;
+    if s.norm_squared() < 1e-12 {
+      return Frame::from_normal(n);
+    }
+    let s = s.normalize();
+    let t = n.cross(s);
+    Frame { s, t, n }
+  }
+
+  pub fn to_local(self, v: Vec3) -> Vec3 {
+    Vec3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+  }
+
+  pub fn to_world(self, v: Vec3) -> Vec3 {
+    v.x() * self.s + v.y() * self.t + v.z() * self.n
+  }
+}
+
+// Below this alpha the distribution is so close to a delta that sampling it
+// becomes numerically unstable.
+const MIN_ALPHA: T = 1e-3;
+
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+  alpha_x: T,
+  alpha_y: T,
+}
+
+impl TrowbridgeReitz {
+  pub fn new(alpha_x: T, alpha_y: T) -> TrowbridgeReitz {
+    TrowbridgeReitz {
+      alpha_x: alpha_x.max(MIN_ALPHA),
+      alpha_y: alpha_y.max(MIN_ALPHA),
+    }
+  }
+
+  // Maps a perceptual roughness in [0, 1] and an anisotropy in [-1, 1] to
+  // the distribution's alphas, following the remapping in Burley's "Physically
+  // Based Shading at Disney" (2012). Positive anisotropy stretches the
+  // highlight along the s tangent of the shading frame.
+  pub fn from_roughness(roughness: T, anisotropy: T) -> TrowbridgeReitz {
+    let alpha = roughness.clamp(0.0, 1.0).powi(2);
+    let aspect = (1.0 - 0.9 * anisotropy.clamp(-1.0, 1.0).abs()).sqrt();
+    if anisotropy >= 0.0 {
+      TrowbridgeReitz::new(alpha / aspect, alpha * aspect)
+    } else {
+      TrowbridgeReitz::new(alpha * aspect, alpha / aspect)
+    }
+  }
+
+  // Smith's auxiliary function, so that G1(w) = 1 / (1 + lambda(w)).
+  pub fn lambda(&self, w: Vec3) -> T {
+    let cos2_theta = w.z() * w.z();
+    if cos2_theta <= 0.0 {
+      return T::INFINITY;
+    }
+    let x = self.alpha_x * w.x();
+    let y = self.alpha_y * w.y();
+    let alpha2_tan2_theta = (x * x + y * y) / cos2_theta;
+    0.5 * ((1.0 + alpha2_tan2_theta).sqrt() - 1.0)
+  }
+
+  pub fn g1(&self, w: Vec3) -> T {
+    1.0 / (1.0 + self.lambda(w))
+  }
+
+  // The height-correlated masking-shadowing function.
+  pub fn g(&self, wo: Vec3, wi: Vec3) -> T {
+    1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+  }
+
+  // Samples a microfacet normal from the distribution of normals visible from
+  // wo, which must be in the upper hemisphere. (u1, u2) are uniform in [0, 1).
+  // See Heitz, "Sampling the GGX Distribution of Visible Normals" (2018).
+  pub fn sample_visible_normal(&self, wo: Vec3, u1: T, u2: T) -> Vec3 {
+    // Stretch the view direction so the distribution becomes a hemisphere.
+    let vh = Vec3::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z())
+      .normalize();
+    let len2 = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if len2 > 0.0 {
+      Vec3::new(-vh.y(), vh.x(), 0.0) / len2.sqrt()
+    } else {
+      Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = vh.cross(t1);
+
+    // Sample the projected area of the hemisphere.
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+    // And unstretch the normal back.
+    Vec3::new(
+      self.alpha_x * nh.x(),
+      self.alpha_y * nh.y(),
+      nh.z().max(1e-6),
+    )
+    .normalize()
+  }
+}
+
+// Schlick's approximation to the Fresnel reflectance of a conductor whose
+// reflectance at normal incidence is f0.
+pub fn fresnel_schlick(cos_theta: T, f0: Color) -> Color {
+  let white = Color::new(1.0, 1.0, 1.0);
+  let m = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+  f0 + m * (white - f0)
+}
+
+// Unpolarized Fresnel reflectance at a dielectric interface. eta is the ratio
+// of the refraction index on the transmitted side to the one on the incident
+// side, and cos_theta_i is measured on the incident side.
+pub fn fresnel_dielectric(cos_theta_i: T, eta: T) -> T {
+  let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+  let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+  if sin2_theta_t >= 1.0 {
+    // Total internal reflection.
+    return 1.0;
+  }
+  let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+  let r_parallel =
+    (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+  let r_perpendicular =
+    (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+  0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::Rng;
+
+  #[test]
+  fn test_shadowing_is_bounded_by_masking() {
+    // The rough materials weight their samples by G2 / G1, which must not
+    // exceed one.
+    let mut rng = rand::thread_rng();
+    let distribution = TrowbridgeReitz::from_roughness(0.7, 0.5);
+    for _ in 0..1000 {
+      let wo = Vec3::new(
+        rng.gen_range(-1.0..1.0),
+        rng.gen_range(-1.0..1.0),
+        rng.gen(),
+      )
+      .normalize();
+      let wi = Vec3::new(
+        rng.gen_range(-1.0..1.0),
+        rng.gen_range(-1.0..1.0),
+        rng.gen(),
+      )
+      .normalize();
+      let g1 = distribution.g1(wo);
+      assert!(g1 <= 1.0);
+      assert!(distribution.g(wo, wi) <= g1);
+    }
+  }
+
+  #[test]
+  fn test_visible_normals_face_the_viewer() {
+    let mut rng = rand::thread_rng();
+    let distribution = TrowbridgeReitz::from_roughness(0.8, -0.3);
+    let wo = Vec3::new(0.6, -0.3, 0.2).normalize();
+    for _ in 0..1000 {
+      let wm = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+      assert!(wm.z() > 0.0);
+      assert!(wm.dot(wo) >= -1e-5);
+      assert!((wm.norm() - 1.0).abs() < 1e-4);
+    }
+  }
+
+  #[test]
+  fn test_frame_round_trip() {
+    let n = Vec3::new(-0.2, 0.5, -0.9).normalize();
+    let frame = Frame::from_normal(n);
+    let v = Vec3::new(0.3, -1.2, 2.0);
+    let round_trip = frame.to_world(frame.to_local(v));
+    assert!((round_trip - v).norm() < 1e-5);
+    assert!((frame.to_local(n).z() - 1.0).abs() < 1e-5);
+  }
+
+  #[test]
+  fn test_fresnel_dielectric() {
+    // Normal incidence on glass reflects about 4%.
+    assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+    // Past the critical angle, light leaving glass is totally reflected.
+    assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+  }
+}
@@ -0,0 +1,184 @@
+// Procedural noise for textures, based on Perlin's "Improving Noise" (2002).
+use crate::vec3::*;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+type T = f32;
+
+// The gradients of improved noise: the midpoints of the edges of a cube.
+const GRADIENTS: [(T, T, T); 12] = [
+  (1.0, 1.0, 0.0),
+  (-1.0, 1.0, 0.0),
+  (1.0, -1.0, 0.0),
+  (-1.0, -1.0, 0.0),
+  (1.0, 0.0, 1.0),
+  (-1.0, 0.0, 1.0),
+  (1.0, 0.0, -1.0),
+  (-1.0, 0.0, -1.0),
+  (0.0, 1.0, 1.0),
+  (0.0, -1.0, 1.0),
+  (0.0, 1.0, -1.0),
+  (0.0, -1.0, -1.0),
+];
+
+pub struct Perlin {
+  // A permutation of 0..256, repeated twice so lookups never wrap.
+  permutation: [u8; 512],
+}
+
+impl Perlin {
+  // Noise with the same seed is the same everywhere, so renders are
+  // repeatable.
+  pub fn new(seed: u64) -> Perlin {
+    let mut p: Vec<u8> = (0..=255).collect();
+    p.shuffle(&mut StdRng::seed_from_u64(seed));
+    let mut permutation = [0; 512];
+    for i in 0..512 {
+      permutation[i] = p[i % 256];
+    }
+    Perlin { permutation }
+  }
+
+  fn hash(&self, x: usize, y: usize, z: usize) -> usize {
+    let p = &self.permutation;
+    p[p[p[x] as usize + y] as usize + z] as usize
+  }
+
+  // Gradient noise in roughly [-1, 1], zero at every integer lattice point.
+  pub fn noise(&self, p: Point) -> T {
+    let v = p.0;
+    let (x, y, z) = (v.x(), v.y(), v.z());
+    let (xf, yf, zf) = (x.floor(), y.floor(), z.floor());
+    let (xi, yi, zi) = (
+      (xf as i32 & 255) as usize,
+      (yf as i32 & 255) as usize,
+      (zf as i32 & 255) as usize,
+    );
+    let (x, y, z) = (x - xf, y - yf, z - zf);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let grad = |dx: usize, dy: usize, dz: usize| -> T {
+      let (gx, gy, gz) =
+        GRADIENTS[self.hash(xi + dx, yi + dy, zi + dz) % GRADIENTS.len()];
+      gx * (x - dx as T) + gy * (y - dy as T) + gz * (z - dz as T)
+    };
+    lerp(
+      w,
+      lerp(
+        v,
+        lerp(u, grad(0, 0, 0), grad(1, 0, 0)),
+        lerp(u, grad(0, 1, 0), grad(1, 1, 0)),
+      ),
+      lerp(
+        v,
+        lerp(u, grad(0, 0, 1), grad(1, 0, 1)),
+        lerp(u, grad(0, 1, 1), grad(1, 1, 1)),
+      ),
+    )
+  }
+
+  // Fractal sum of octaves of noise, each twice the frequency and half the
+  // amplitude of the one before.
+  pub fn fbm(&self, p: Point, octaves: u32) -> T {
+    self.sum_octaves(p, octaves, |n| n)
+  }
+
+  // Like fbm, but summing the absolute value of each octave, which gives the
+  // creases typical of turbulence. The result is in roughly [0, 1].
+  pub fn turbulence(&self, p: Point, octaves: u32) -> T {
+    self.sum_octaves(p, octaves, |n| n.abs())
+  }
+
+  fn sum_octaves<F: Fn(T) -> T>(&self, p: Point, octaves: u32, f: F) -> T {
+    let mut sum = 0.0;
+    let mut weight = 1.0;
+    let mut p = p;
+    for _ in 0..octaves.max(1) {
+      sum += weight * f(self.noise(p));
+      weight *= 0.5;
+      p = 2.0 * p;
+    }
+    sum
+  }
+}
+
+fn fade(t: T) -> T {
+  t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: T, a: T, b: T) -> T {
+  a + t * (b - a)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum NoisePattern {
+  // Smooth noise.
+  Plain,
+  // Fractal noise with sharp creases.
+  Turbulence,
+  // Veins of turbulence perturbing stripes along z.
+  Marble,
+  // Turbulent rings around the y axis.
+  Wood,
+}
+
+// A grayscale noise texture. Points are scaled by scale before evaluating the
+// noise, so larger scales give finer detail.
+pub struct Noise {
+  pub perlin: Perlin,
+  pub pattern: NoisePattern,
+  pub scale: T,
+  pub octaves: u32,
+}
+
+impl Noise {
+  // The value of the pattern at a point, in [0, 1].
+  pub fn value(&self, p: Point) -> T {
+    let q = self.scale * p;
+    let value = match self.pattern {
+      NoisePattern::Plain => 0.5 * (1.0 + self.perlin.fbm(q, self.octaves)),
+      NoisePattern::Turbulence => self.perlin.turbulence(q, self.octaves),
+      NoisePattern::Marble => {
+        let turbulence = self.perlin.turbulence(q, self.octaves);
+        0.5 * (1.0 + (q.0.z() + 10.0 * turbulence).sin())
+      }
+      NoisePattern::Wood => {
+        let v = q.0;
+        let rings = (v.x() * v.x() + v.z() * v.z()).sqrt()
+          + 0.5 * self.perlin.turbulence(q, self.octaves);
+        let grain = (4.0 * rings).fract();
+        // Sharpen the rings into thin dark bands.
+        1.0 - grain.powi(4)
+      }
+    };
+    value.clamp(0.0, 1.0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_noise_vanishes_on_the_lattice() {
+    let perlin = Perlin::new(7);
+    for &(x, y, z) in &[(0.0, 0.0, 0.0), (3.0, -2.0, 5.0), (-17.0, 4.0, 250.0)]
+    {
+      assert!(perlin.noise(Point::new(x, y, z)).abs() < 1e-6);
+    }
+  }
+
+  #[test]
+  fn test_noise_is_bounded_and_repeatable() {
+    let a = Perlin::new(7);
+    let b = Perlin::new(7);
+    for i in 0..1000 {
+      let t = i as T * 0.137;
+      let p = Point::new(t, 0.5 * t - 3.0, 2.0 - 0.3 * t);
+      let n = a.noise(p);
+      assert!((-1.0..=1.0).contains(&n));
+      assert_eq!(n, b.noise(p));
+    }
+  }
+}
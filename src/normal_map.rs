@@ -0,0 +1,187 @@
+// Normal and bump mapping, which add detail to how a surface shades without
+// changing its geometry, by perturbing the normal materials scatter about.
+use crate::microfacet::Frame;
+use crate::object::*;
+use crate::texture::*;
+use crate::vec3::*;
+
+type T = f32;
+
+#[derive(Clone)]
+pub enum NormalMap {
+  // A tangent space normal map. Each texel's channels, mapped from [0, 1] to
+  // [-1, 1], are a normal along (dp/du, dp/dv, n), so unperturbed texels are
+  // (0.5, 0.5, 1). Its image should be loaded as linear.
+  Tangent(Texture),
+  // A height field that displaces the surface along its normal by scale
+  // times the texture's value.
+  Bump { height: Texture, scale: T },
+}
+
+impl NormalMap {
+  // The perturbed normal at a hit, on the same side of the surface as
+  // hit.normal.
+  pub fn shading_normal(&self, hit: &HitResultPayload) -> Vec3 {
+    // Work with the outward normal, so maps look the same from either side.
+    let n = if hit.front_face {
+      hit.normal
+    } else {
+      -hit.normal
+    };
+    let perturbed = match self {
+      NormalMap::Tangent(texture) => tangent_normal(texture, n, hit),
+      NormalMap::Bump { height, scale } => bump_normal(height, *scale, n, hit),
+    };
+    if hit.front_face {
+      perturbed
+    } else {
+      -perturbed
+    }
+  }
+}
+
+fn tangent_normal(texture: &Texture, n: Vec3, hit: &HitResultPayload) -> Vec3 {
+  let c = texture.sample(hit);
+  let local =
+    Vec3::new(2.0 * c.r() - 1.0, 2.0 * c.g() - 1.0, 2.0 * c.b() - 1.0);
+  if local.near_zero() {
+    return n;
+  }
+  // Objects without derivatives get an arbitrary tangent, which is fine for
+  // maps without a preferred direction.
+  let tangent = hit.dpdu - n.dot(hit.dpdu) * n;
+  if tangent.near_zero() {
+    return Frame::from_normal(n).to_world(local).normalize();
+  }
+  let s = tangent.normalize();
+  let mut t = n.cross(s);
+  // Keep the map's v axis along dp/dv, whichever way the surface is wound.
+  if t.dot(hit.dpdv) < 0.0 {
+    t = -t;
+  }
+  (local.x() * s + local.y() * t + local.z() * n).normalize()
+}
+
+// As in pbrt's BumpMap: the displaced surface p + h n has derivatives
+// dp/du + dh/du n (ignoring how n itself changes), and its normal is their
+// cross product. The height's derivatives are taken by finite differences
+// over the hit's footprint.
+fn bump_normal(
+  height: &Texture,
+  scale: T,
+  n: Vec3,
+  hit: &HitResultPayload,
+) -> Vec3 {
+  let tc = TexCoord::from_hit(hit);
+  let step = |footprint: T| {
+    if footprint > 0.0 {
+      0.5 * footprint
+    } else {
+      0.0005
+    }
+  };
+  let (du, dv) = (step(tc.du), step(tc.dv));
+  let h = |u: T, v: T, offset: Vec3| {
+    let c = height.value(&TexCoord {
+      u,
+      v,
+      p: tc.p + offset,
+      object_p: tc.object_p + offset,
+      ..tc
+    });
+    scale * (c.r() + c.g() + c.b()) / 3.0
+  };
+  let zero = Vec3::new(0.0, 0.0, 0.0);
+  let h0 = h(tc.u, tc.v, zero);
+  let dhdu = (h(tc.u + du, tc.v, du * hit.dpdu) - h0) / du;
+  let dhdv = (h(tc.u, tc.v + dv, dv * hit.dpdv) - h0) / dv;
+  let dpdu = hit.dpdu + dhdu * n;
+  let dpdv = hit.dpdv + dhdv * n;
+  let perturbed = dpdu.cross(dpdv);
+  if perturbed.near_zero() {
+    return n;
+  }
+  let perturbed = perturbed.normalize();
+  if perturbed.dot(n) < 0.0 {
+    -perturbed
+  } else {
+    perturbed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::image_texture::*;
+  use crate::material2::*;
+  use crate::ray::*;
+  use image::{DynamicImage, ImageBuffer, Rgb};
+  use std::sync::Arc;
+
+  fn hit_on_plane(material: &Material) -> HitResultPayload<'_> {
+    let ray = Ray {
+      origin: Point::new(0.3, 0.4, 1.0),
+      direction: Vec3::new(0.0, 0.0, -1.0),
+      time: 0.0,
+      wavelength: None,
+      cone: RayCone {
+        width: 0.0,
+        spread: 0.0,
+      },
+      media: Media::none(),
+    };
+    HitResultPayload::new(
+      Point::new(0.3, 0.4, 0.0),
+      &ray,
+      Vec3::new(0.0, 0.0, 1.0),
+      material,
+      0.3,
+      0.4,
+    )
+    .with_derivatives(Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0))
+  }
+
+  #[test]
+  fn test_tangent_normal_map() {
+    let material = Material::new_lambertian(Texture::constant(0.5));
+    let hit = hit_on_plane(&material);
+    let flat = NormalMap::Tangent(Texture::Color(Color::new(0.5, 0.5, 1.0)));
+    assert!((flat.shading_normal(&hit) - hit.normal).norm() < 1e-6);
+    // Tilted toward +u.
+    let tilted = NormalMap::Tangent(Texture::Color(Color::new(1.0, 0.5, 1.0)));
+    let n = tilted.shading_normal(&hit);
+    let expected = Vec3::new(1.0, 0.0, 1.0).normalize();
+    assert!((n - expected).norm() < 1e-6);
+  }
+
+  #[test]
+  fn test_bump_map_follows_the_slope() {
+    let material = Material::new_lambertian(Texture::constant(0.5));
+    let hit = hit_on_plane(&material);
+    // A constant height leaves the normal alone.
+    let flat = NormalMap::Bump {
+      height: Texture::constant(0.7),
+      scale: 1.0,
+    };
+    assert!((flat.shading_normal(&hit) - hit.normal).norm() < 1e-6);
+    // A height rising along u by one unit per unit of u tilts the normal
+    // back toward -u, at 45 degrees.
+    let ramp = ImageBuffer::from_fn(256, 1, |x, _| {
+      let h = ((x as T + 0.5) / 256.0 * 65535.0).round() as u16;
+      Rgb([h, h, h])
+    });
+    let ramp = ImageTexture::new(
+      &DynamicImage::ImageRgb16(ramp),
+      ColorSpace::Linear,
+      Filter::Bilinear,
+      WrapMode::Clamp,
+    );
+    let sloped = NormalMap::Bump {
+      height: Texture::Image(Arc::new(ramp)),
+      scale: 1.0,
+    };
+    let n = sloped.shading_normal(&hit);
+    let expected = Vec3::new(-1.0, 0.0, 1.0).normalize();
+    assert!((n - expected).norm() < 1e-2);
+  }
+}
@@ -1,19 +1,31 @@
 use crate::aabb::*;
+use crate::animation::*;
 use crate::material2::*;
+use crate::quaternion::*;
 use crate::ray::*;
 use crate::vec3::*;
 use std::f32::consts::PI;
+use std::sync::Arc;
 
 type T = f32;
 
 pub struct HitResultPayload<'a> {
   pub p: Point,
+  // The hit point relative to the object's origin, such as a sphere's
+  // center. Objects that don't say have their origin at the world's.
+  pub object_p: Point,
   pub normal: Vec3,
   pub front_face: bool,
   pub material: &'a Material,
   // u, v are surface coordinats for this hit.
   pub u: T,
-  pub v: T
+  pub v: T,
+  // The partial derivatives of the hit point with respect to u and v. They
+  // are zero for objects that don't provide them.
+  pub dpdu: Vec3,
+  pub dpdv: Vec3,
+  // The width of the area around p covered by the ray that hit it.
+  pub footprint: T,
 }
 
 pub struct HitResult<'a> {
@@ -22,9 +34,9 @@ pub struct HitResult<'a> {
 }
 
 impl HitResult<'_> {
-  pub fn new<'a>(
+  pub fn new(
     t: T,
-    obj: &'a dyn Object) -> HitResult {
+    obj: &dyn Object) -> HitResult<'_> {
     HitResult { t, obj }
   }
 }
@@ -46,35 +58,116 @@ impl HitResultPayload<'_> {
       } else {
         -outward_normal
       };
+    let zero = Vec3::new(0.0, 0.0, 0.0);
     HitResultPayload {
       p,
+      object_p: p,
       normal,
       front_face,
       material,
       u,
-      v
+      v,
+      dpdu: zero,
+      dpdv: zero,
+      footprint: r.cone.width_at((p - r.origin).norm()),
     }
   }
+
+  pub fn with_derivatives(mut self, dpdu: Vec3, dpdv: Vec3) -> Self {
+    self.dpdu = dpdu;
+    self.dpdv = dpdv;
+    self
+  }
+
+  pub fn with_object_origin(mut self, origin: Point) -> Self {
+    self.object_p = Point(self.p - origin);
+    self
+  }
+
+  // How much of the (u, v) square the ray's footprint covers along each
+  // direction, or zero if the object provides no derivatives.
+  pub fn uv_footprint(&self) -> (T, T) {
+    let along = |d: Vec3| {
+      let norm = d.norm();
+      if norm > 0.0 {
+        self.footprint / norm
+      } else {
+        0.0
+      }
+    };
+    (along(self.dpdu), along(self.dpdv))
+  }
+}
+
+// Surface coordinates and their derivatives on a sphere, given the outward
+// unit normal at the hit point.
+//
+// If we treat the normal as a point, it becomes the point at which this light
+// ray would've hit the sphere, had the sphere been centered at the origin. We
+// can then use its coordinates to figure out the spherical coordinates for the
+// hit, for such an origin-centered sphere.
+fn sphere_uv(normal: Vec3, radius: T) -> (T, T, Vec3, Vec3) {
+  let theta = (-normal.y()).acos();
+  let phi = (-normal.z()).atan2(normal.x()) + PI;
+  let u = phi / (2.0 * PI);
+  let v = theta / PI;
+
+  let sin_theta = (1.0 - normal.y() * normal.y()).sqrt().max(1e-6);
+  let dpdu = (2.0 * PI * radius) * Vec3::new(normal.z(), 0.0, -normal.x());
+  let dpdv = (PI * radius)
+    * Vec3::new(
+      -normal.y() * normal.x() / sin_theta,
+      sin_theta,
+      -normal.y() * normal.z() / sin_theta,
+    );
+  (u, v, dpdu, dpdv)
 }
 
 pub trait Object {
-  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult>;
-  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload;
+  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult<'_>>;
+  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload<'_>;
   fn bounding_box(&self, time0: T, time1: T) -> Option<BoundingBox>;
+
+  // The material the object is made of, if it's a single object.
+  fn material(&self) -> Option<&Material> {
+    None
+  }
+
+  // Where the point that the ray hits at t is at another time, for motion
+  // vectors. Objects that don't move leave it where it is.
+  fn position_at(&self, t: T, ray: &Ray, _time: T) -> Point {
+    ray.at(t)
+  }
+}
+
+// Whether the hit at t falls in a hole of the object's alpha cutout, if its
+// material has one. Objects call this from hit, so rays carry on through the
+// holes to whatever is behind them.
+pub fn is_cut_out(
+  obj: &dyn Object,
+  material: &Material,
+  t: T,
+  ray: &Ray,
+) -> bool {
+  material.has_cutout() && material.is_cut_out(&obj.hit_payload(t, ray))
 }
 
 pub struct Sphere {
   center: Point,
   radius: T,
-  material: Material,
+  material: Arc<Material>,
 }
 
 impl Sphere {
-  pub fn new(center: Point, radius: T, material: Material) -> Sphere {
+  pub fn new(
+    center: Point,
+    radius: T,
+    material: impl Into<Arc<Material>>,
+  ) -> Sphere {
     Sphere {
       center,
       radius,
-      material,
+      material: material.into(),
     }
   }
 }
@@ -83,7 +176,7 @@ impl Sphere {
 }
 
 impl Object for Sphere {
-  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult> {
+  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult<'_>> {
     let oc: Vec3 = ray.origin - self.center;
     let a = ray.direction.norm_squared();
     let half_b = oc.dot(ray.direction);
@@ -93,7 +186,9 @@ impl Object for Sphere {
       return None;
     }
     let sqrtd = discriminant.sqrt();
-    let valid_t = |t: T| -> bool { t >= t_min && t <= t_max };
+    let valid_t = |t: T| -> bool {
+      t >= t_min && t <= t_max && !is_cut_out(self, &self.material, t, ray)
+    };
     let mut root = (-half_b - sqrtd) / a;
     if !valid_t(root) {
       root = (-half_b + sqrtd) / a;
@@ -101,24 +196,17 @@ impl Object for Sphere {
         return None;
       }
     }
-    return Some(HitResult { t: root, obj: self });
+    Some(HitResult { t: root, obj: self })
   }
-  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload {
+  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload<'_> {
     assert!(t >= 0.0);
     let point = ray.at(t);
     let mut normal = point - self.center;
     normal /= self.radius;
-
-    // If we treat the normal as a point, it becomes the point at which this light ray would've
-    // hit the sphere, had the sphere been centered at the origin. We can then use its
-    // coordinates to figure out the spherical coordinates for the hit, for such an
-    // origin-centered sphere.
-    let theta = (-normal.y()).acos();
-    let phi = (-normal.z()).atan2(normal.x()) + PI;
-
-    let u = phi / (2.0 * PI);
-    let v = theta / PI;
+    let (u, v, dpdu, dpdv) = sphere_uv(normal, self.radius);
     HitResultPayload::new(point, ray, normal, &self.material, u, v)
+      .with_derivatives(dpdu, dpdv)
+      .with_object_origin(self.center)
   }
   fn bounding_box(&self, _time0: T, _time1: T) -> Option<BoundingBox> {
     let v = Vec3::new(self.radius, self.radius, self.radius);
@@ -127,6 +215,9 @@ impl Object for Sphere {
       self.center + v
     ))
   }
+  fn material(&self) -> Option<&Material> {
+    Some(&self.material)
+  }
 }
 
 pub struct MovingSphere {
@@ -135,7 +226,7 @@ pub struct MovingSphere {
   time0: T,
   time1: T,
   radius: T,
-  material: Material,
+  material: Arc<Material>,
 }
 
 impl MovingSphere {
@@ -145,7 +236,7 @@ impl MovingSphere {
     time0: T,
     time1: T,
     radius: T,
-    material: Material,
+    material: impl Into<Arc<Material>>,
   ) -> MovingSphere {
     MovingSphere {
       center0,
@@ -153,7 +244,7 @@ impl MovingSphere {
       time0,
       time1,
       radius,
-      material,
+      material: material.into(),
     }
   }
   pub fn center(&self, time: T) -> Point {
@@ -164,7 +255,7 @@ impl MovingSphere {
 }
 
 impl Object for MovingSphere {
-  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult> {
+  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult<'_>> {
     let oc: Vec3 = ray.origin - self.center(ray.time);
     let a = ray.direction.norm_squared();
     let half_b = oc.dot(ray.direction);
@@ -174,7 +265,9 @@ impl Object for MovingSphere {
       return None;
     }
     let sqrtd = discriminant.sqrt();
-    let valid_t = |t: T| -> bool { t >= t_min && t <= t_max };
+    let valid_t = |t: T| -> bool {
+      t >= t_min && t <= t_max && !is_cut_out(self, &self.material, t, ray)
+    };
     let mut root = (-half_b - sqrtd) / a;
     if !valid_t(root) {
       root = (-half_b + sqrtd) / a;
@@ -185,17 +278,15 @@ impl Object for MovingSphere {
     Some(HitResult::new(root, self))
   }
 
-  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload {
+  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload<'_> {
     assert!(t >= 0.0);
     let point = ray.at(t);
-    let normal = (point - self.center(ray.time)) / self.radius;
-    // See the Sphere hit subroutine for why we compute u, v this way.
-    let theta = (-normal.y()).acos();
-    let phi = (-normal.z()).atan2(normal.x()) + PI;
-
-    let u = phi / (2.0 * PI);
-    let v = theta / PI;
+    let center = self.center(ray.time);
+    let normal = (point - center) / self.radius;
+    let (u, v, dpdu, dpdv) = sphere_uv(normal, self.radius);
     HitResultPayload::new(point, ray, normal, &self.material, u, v)
+      .with_derivatives(dpdu, dpdv)
+      .with_object_origin(center)
   }
   
   fn bounding_box(&self, time0: T, time1: T) -> Option<BoundingBox> {
@@ -210,19 +301,295 @@ impl Object for MovingSphere {
     );
     Some(BoundingBox::surrounding_box(&bb0, &bb1))
   }
+
+  fn material(&self) -> Option<&Material> {
+    Some(&self.material)
+  }
+
+  fn position_at(&self, t: T, ray: &Ray, time: T) -> Point {
+    ray.at(t) + (self.center(time) - self.center(ray.time))
+  }
+}
+
+// A parallelogram with a corner at q and sides u and v. Surface coordinates
+// go from 0 to 1 along each side.
+pub struct Quad {
+  q: Point,
+  u: Vec3,
+  v: Vec3,
+  // The unit normal, and the plane's offset along it.
+  normal: Vec3,
+  d: T,
+  // Maps points on the plane to (u, v) coordinates, as in Shirley's "Ray
+  // Tracing: The Next Week".
+  w: Vec3,
+  material: Arc<Material>,
+}
+
+impl Quad {
+  pub fn new(
+    q: Point,
+    u: Vec3,
+    v: Vec3,
+    material: impl Into<Arc<Material>>,
+  ) -> Quad {
+    let n = u.cross(v);
+    let normal = n.normalize();
+    Quad {
+      q,
+      u,
+      v,
+      normal,
+      d: normal.dot(q.0),
+      w: n / n.dot(n),
+      material: material.into(),
+    }
+  }
+
+  // The surface coordinates of a point on the quad's plane.
+  fn uv(&self, p: Point) -> (T, T) {
+    let planar = p - self.q;
+    (
+      self.w.dot(planar.cross(self.v)),
+      self.w.dot(self.u.cross(planar)),
+    )
+  }
+}
+
+impl Object for Quad {
+  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult<'_>> {
+    let denominator = self.normal.dot(ray.direction);
+    if denominator.abs() < 1e-8 {
+      return None;
+    }
+    let t = (self.d - self.normal.dot(ray.origin.0)) / denominator;
+    if t < t_min || t > t_max {
+      return None;
+    }
+    let (u, v) = self.uv(ray.at(t));
+    let inside = |x: T| (0.0..=1.0).contains(&x);
+    if !inside(u) || !inside(v) || is_cut_out(self, &self.material, t, ray) {
+      return None;
+    }
+    Some(HitResult::new(t, self))
+  }
+
+  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload<'_> {
+    let point = ray.at(t);
+    let (u, v) = self.uv(point);
+    HitResultPayload::new(point, ray, self.normal, &self.material, u, v)
+      .with_derivatives(self.u, self.v)
+      .with_object_origin(self.q)
+  }
+
+  fn bounding_box(&self, _time0: T, _time1: T) -> Option<BoundingBox> {
+    let corners = [self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+    let (mut lo, mut hi) = (self.q.0, self.q.0);
+    for c in &corners {
+      lo = lo.min(c.0);
+      hi = hi.max(c.0);
+    }
+    // Pad the box, so it has some thickness along the quad's normal.
+    let padding = Vec3::new(1e-4, 1e-4, 1e-4);
+    Some(BoundingBox::new(Point(lo - padding), Point(hi + padding)))
+  }
+
+  fn material(&self) -> Option<&Material> {
+    Some(&self.material)
+  }
+}
+
+// An object moved along a keyframed path. At each time its points are
+// scaled, then rotated, about its origin, then translated.
+pub struct Animated {
+  object: Box<dyn Object + Send + Sync>,
+  translation: Track<Vec3>,
+  rotation: Track<Quaternion>,
+  // Scaling is the same along every axis, so normals only need rotating.
+  scale: Track<T>,
+}
+
+impl Animated {
+  pub fn new(
+    object: Box<dyn Object + Send + Sync>,
+    translation: Track<Vec3>,
+  ) -> Animated {
+    Animated {
+      object,
+      translation,
+      rotation: Track::constant(Quaternion::identity()),
+      scale: Track::constant(1.0),
+    }
+  }
+
+  pub fn with_rotation(mut self, rotation: Track<Quaternion>) -> Animated {
+    self.rotation = rotation;
+    self
+  }
+
+  pub fn with_scale(mut self, scale: Track<T>) -> Animated {
+    self.scale = scale;
+    self
+  }
+
+  // The ray in the object's own space. Its direction is scaled along with
+  // its origin, so distances along it stay the same.
+  fn local_ray(&self, ray: &Ray) -> Ray {
+    let inverse = self.rotation.at(ray.time).conjugate();
+    let scale = self.scale.at(ray.time);
+    let origin = ray.origin - self.translation.at(ray.time);
+    Ray {
+      origin: Point(inverse.rotate(origin.0) / scale),
+      direction: inverse.rotate(ray.direction) / scale,
+      ..*ray
+    }
+  }
+
+  // Takes a point in the object's space to the world's, at time.
+  fn to_world(&self, p: Point, time: T) -> Point {
+    let scaled = self.scale.at(time) * p.0;
+    Point(self.rotation.at(time).rotate(scaled) + self.translation.at(time))
+  }
+}
+
+impl Object for Animated {
+  fn hit(&self, t_min: T, t_max: T, ray: &Ray) -> Option<HitResult<'_>> {
+    let hit = self.object.hit(t_min, t_max, &self.local_ray(ray))?;
+    Some(HitResult::new(hit.t, self))
+  }
+
+  fn hit_payload(&self, t: T, ray: &Ray) -> HitResultPayload<'_> {
+    let rotation = self.rotation.at(ray.time);
+    let scale = self.scale.at(ray.time);
+    let mut payload = self.object.hit_payload(t, &self.local_ray(ray));
+    // The object's own point is left in its space, so its textures move
+    // with it.
+    payload.p = self.to_world(payload.p, ray.time);
+    payload.normal = rotation.rotate(payload.normal);
+    payload.dpdu = scale * rotation.rotate(payload.dpdu);
+    payload.dpdv = scale * rotation.rotate(payload.dpdv);
+    payload.footprint = ray.cone.width_at((payload.p - ray.origin).norm());
+    payload
+  }
+
+  // Bounds the object wherever it goes from time0 to time1. The corners of
+  // its own box are where it can reach while it holds still, and a ball
+  // around its origin holds them at any rotation.
+  fn bounding_box(&self, time0: T, time1: T) -> Option<BoundingBox> {
+    let bb = self.object.bounding_box(time0, time1)?;
+    let rotations = self.rotation.hull(time0, time1);
+    let scales = self.scale.hull(time0, time1);
+    let translations = self.translation.hull(time0, time1);
+    let rotating = rotations.iter().any(|q| q.angle_to(rotations[0]) > 1e-6);
+    let max_scale = scales.iter().cloned().fold(0.0, T::max);
+    let radius = bb.corners().iter().map(|c| c.0.norm()).fold(0.0, T::max);
+    let reach = Vec3::new(1.0, 1.0, 1.0) * (max_scale * radius);
+    let mut points = Vec::new();
+    for &translation in &translations {
+      if rotating {
+        points.push(Point(translation - reach));
+        points.push(Point(translation + reach));
+        continue;
+      }
+      for &scale in &scales {
+        for corner in &bb.corners() {
+          points
+            .push(Point(rotations[0].rotate(scale * corner.0) + translation));
+        }
+      }
+    }
+    BoundingBox::around(points)
+  }
+
+  fn material(&self) -> Option<&Material> {
+    self.object.material()
+  }
+
+  fn position_at(&self, t: T, ray: &Ray, time: T) -> Point {
+    let p = self.object.position_at(t, &self.local_ray(ray), time);
+    self.to_world(p, time)
+  }
 }
 
 pub struct ObjectList {
   pub objects: Vec<Option<Box<dyn Object + Sync + Send>>>,
+  // Each object's index, counting from 1 in the order they were added.
+  // Objects added together share one.
+  pub indices: Vec<u32>,
 }
 
 impl ObjectList {
   pub fn new() -> ObjectList {
     ObjectList {
       objects: Vec::new(),
+      indices: Vec::new(),
     }
   }
   pub fn add(&mut self, obj: Box<dyn Object + Sync + Send>) {
-    self.objects.push(Some(obj));
+    self.add_all(vec![obj]);
+  }
+  // Adds the parts of a single thing, such as a mesh's triangles.
+  pub fn add_all(&mut self, objs: Vec<Box<dyn Object + Sync + Send>>) {
+    let index = self.indices.last().map_or(1, |i| i + 1);
+    for obj in objs {
+      self.objects.push(Some(obj));
+      self.indices.push(index);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::texture::*;
+
+  fn ray_at(x: T, y: T) -> Ray {
+    Ray {
+      origin: Point::new(x, y, 1.0),
+      direction: Vec3::new(0.0, 0.0, -1.0),
+      time: 0.0,
+      wavelength: None,
+      cone: RayCone {
+        width: 0.0,
+        spread: 0.0,
+      },
+      media: Media::none(),
+    }
+  }
+
+  #[test]
+  fn test_quad_hits_and_cutouts() {
+    let material = Material::new_lambertian(Texture::constant(0.5));
+    let quad = Quad::new(
+      Point::new(0.0, 0.0, 0.1),
+      Vec3::new(2.0, 0.0, 0.0),
+      Vec3::new(0.0, 1.0, 0.0),
+      material.clone(),
+    );
+    let hit = quad.hit(0.001, 10.0, &ray_at(0.5, 0.25)).unwrap();
+    assert!((hit.t - 0.9).abs() < 1e-6);
+    let payload = quad.hit_payload(hit.t, &ray_at(0.5, 0.25));
+    assert!((payload.u - 0.25).abs() < 1e-6 && (payload.v - 0.25).abs() < 1e-6);
+    assert!(quad.hit(0.001, 10.0, &ray_at(2.5, 0.25)).is_none());
+
+    // Fully transparent where the checkers are black.
+    let checkers = Texture::new_checkers(
+      Texture::constant(1.0),
+      Texture::constant(0.0),
+      0.1,
+    );
+    let cutout = Quad::new(
+      Point::new(0.0, 0.0, 0.1),
+      Vec3::new(2.0, 0.0, 0.0),
+      Vec3::new(0.0, 1.0, 0.0),
+      material.with_cutout(checkers, AlphaMode::Threshold(0.5)),
+    );
+    let hits = (0..100)
+      .filter(|i| {
+        let x = 0.02 * *i as T;
+        cutout.hit(0.001, 10.0, &ray_at(x, 0.5)).is_some()
+      })
+      .count();
+    assert!(hits > 0 && hits < 100);
   }
 }
@@ -0,0 +1,656 @@
+// Command line options for a render.
+use crate::animation::*;
+use crate::aov::*;
+use crate::aperture::*;
+use crate::camera::*;
+use crate::canvas::*;
+use crate::caption::*;
+use crate::denoise::*;
+use crate::image_texture::*;
+use crate::post::*;
+use crate::regularization::*;
+use std::sync::Arc;
+
+pub const USAGE: &str = "\
+Usage: raytracer [options]
+
+Options:
+  --scene <name>            What to render: spheres (the default), or
+                            showcase, the same spheres made of the newer
+                            materials and textures, among other things.
+  --spectral                Trace wavelengths instead of RGB, for dispersion.
+  --samples <n>             Samples per pixel. Defaults to 100.
+  --denoise <filter>        Denoise the image, guided by the albedo, normal
+                            and depth of what each pixel sees: atrous for
+                            an a-trous wavelet filter, or nlm for non-local
+                            means, which is slower but keeps finer detail.
+  --keep-noisy              Also save the image from before it's denoised,
+                            as scene_noisy.png.
+  --clamp-direct <max>      Scale down samples brighter than this, in
+                            luminance, whose light bounced at most once.
+  --clamp-indirect <max>    Likewise for light that bounced more, which is
+                            where most fireflies come from.
+  --reject-outliers <n>     Drop samples brighter than the rest of their
+                            pixel's by n standard deviations, such as 3.
+  --regularize <roughness>  Make surfaces at least this rough (0 to 1) after
+                            the first bounce, blurring caustics from glass
+                            and metal so they converge. What's seen in
+                            them blurs too, so keep it small, such as 0.1.
+  --bloom <strength>[:<radius>]
+                            Spread light brighter than the highlight
+                            threshold into a glow, blurring it by up to
+                            radius image heights (0.02 by default).
+  --glare <strength>[:<points>]
+                            Streak light brighter than the highlight
+                            threshold into stars of 6 or the given number
+                            of points.
+  --highlight-threshold <luminance>
+                            What blooms and glares. Defaults to 1, the
+                            brightest a PNG shows.
+  --chromatic-aberration <pixels>
+                            Separate red and blue towards the edges, as a
+                            lens that images them at different sizes does,
+                            by this many pixels at the corners.
+  --vignette <amount>       Darken the corners by this fraction, from 0 to
+                            1.
+  --caption <text>          Burn a caption into the image, where {scene},
+                            {frame}, {spp} and {time} are replaced by the
+                            scene's name, the frame number, the samples per
+                            pixel and how long the render took.
+  --caption-font <file>     The caption's TrueType font, such as
+                            /usr/share/fonts/truetype/dejavu/DejaVuSans.ttf.
+                            Captions need one.
+  --caption-size <pixels>   The caption's line height. Defaults to 16.
+  --caption-color <r>,<g>,<b>
+                            The caption's color, from 0 to 1. Defaults to
+                            white.
+  --caption-position <corner>
+                            Where the caption goes: top-left, top-right,
+                            bottom-left (the default) or bottom-right.
+                            Any of these options adds a caption of all four
+                            facts, if --caption doesn't give its text. PNG
+                            images keep them as text chunks either way.
+  --texture-filter <f>      Filtering for image textures: nearest, bilinear
+                            or trilinear (the default).
+  --texture-wrap <w>        Wrapping for image textures: clamp (the
+                            default), repeat or mirror.
+  --camera <c>              The camera's projection: perspective (the
+                            default), orthographic, fisheye or
+                            equirectangular.
+  --fov <degrees>           The vertical field of view. Defaults to 20, or
+                            180 for fisheye cameras. Only fisheye and
+                            equirectangular cameras take 180 or more.
+  --bokeh <shape>           The shape of the lens's opening: disk (the
+                            default), blades:<n>[:<degrees>] for a polygon
+                            of n blades, turned by degrees, or image:<path>.
+  --f-stop <n>              Use a photographic camera, with this f-number
+                            (1.4 by default). Its lens and the image's
+                            brightness follow from the f-number, shutter
+                            speed and ISO, taking scene units as metres and
+                            radiance as cd/m^2.
+  --shutter <seconds>       The shutter speed, such as 1/60. Defaults to 1/4.
+                            When given, the shutter is open that long from
+                            time 0 for a still image, or for that much of
+                            each frame of an animation, in place of
+                            --shutter-interval and --shutter-angle.
+  --iso <speed>             The ISO speed. Defaults to 800.
+  --lens <file>             Trace rays through the lens in a prescription
+                            file, such as lenses/dgauss50.dat, instead of
+                            a thin lens. Its focal length sets the field of
+                            view.
+  --frames <frames>         Render frames of the animation as numbered
+                            images, such as scene_0001.png: a range such as
+                            10-20, or a count to render from frame 0.
+  --fps <rate>              Frames per second. Defaults to 24.
+  --shutter-angle <degrees> How much of each frame the shutter is open for:
+                            360 (the most) for all of it, 180 (the
+                            default) for half.
+  --shutter-offset <degrees>
+                            When in each frame the shutter opens. Defaults
+                            to 0, at the frame's start; -90 centers a 180
+                            degree shutter on it.
+  --shutter-interval <open>:<close>
+                            When the shutter is open for a still image.
+                            Defaults to 0:1.
+  --shutter-curve <curve>   How the shutter opens and closes: box (the
+                            default, at once), triangle, or
+                            trapezoid:<ramp> to open and close over that
+                            fraction of the time it's open.
+  --stereo <layout>         Render a stereo pair: side-by-side, top-bottom
+                            (left eye first, in one image) or separate
+                            (scene_left.png and scene_right.png).
+                            Equirectangular cameras render omni-directional
+                            stereo.
+  --ipd <distance>          The distance between the eyes, in scene units.
+                            Defaults to 0.065.
+  --crop <x0>,<x1>,<y0>,<y1>
+                            Render only this part of the image, in fractions
+                            of its width and height from the top left, such
+                            as 0.25,0.75,0,0.5. Other pixels are black.
+  --pixel-bounds <x0>,<x1>,<y0>,<y1>
+                            Like --crop, in pixels, excluding x1 and y1.
+  --crop-only               Save just the cropped part of the image.
+  --composite               Render the crop into the image already saved
+                            under the same name, as by an earlier render.
+  --format <f>              The images' format: png (the default), hdr for
+                            linear Radiance files to composite into, or pfm
+                            for 32 bit float maps, which keep negative
+                            values too.
+  --aov <names>             Also save images of what camera rays hit first,
+                            such as scene_normal.png, for compositing: a
+                            comma separated list of position, depth,
+                            normal, albedo, object-id, material-id, uv and
+                            motion, or all. Position, depth, normal, uv and
+                            motion are measurements, which can be negative
+                            or above 1, so they're always saved as .pfm
+                            float maps, such as scene_depth.pfm, in place
+                            of OpenEXR, which this can't write. The others
+                            take --format. Object and material IDs are
+                            those of each pixel's first sample.
+  --ground-texture <expr>   A texture expression for the ground, such as
+                            \"mix(0.2, rgb(0.5, 0.3, 0.1), noise(marble, 2))\".
+                            See texture_expr.rs for the nodes.
+  --help                    Print this message.";
+
+// The scenes there are to render.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scene {
+  // The final scene of Ray Tracing in One Weekend.
+  Spheres,
+  // The same layout, showing off materials, textures and animation.
+  Showcase,
+}
+
+impl Scene {
+  // The scene's name, for captions and metadata.
+  pub fn name(&self) -> &'static str {
+    match self {
+      Scene::Spheres => "spheres",
+      Scene::Showcase => "showcase",
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+  pub scene: Scene,
+  pub spectral: bool,
+  pub samples_per_pixel: u32,
+  pub denoiser: Option<Denoiser>,
+  pub keep_noisy: bool,
+  pub regularization: Regularization,
+  // Applied to the beauty image only, after any denoising.
+  pub post: PostEffects,
+  pub caption: Option<Caption>,
+  pub texture_filter: Filter,
+  pub texture_wrap: WrapMode,
+  pub ground_texture: Option<String>,
+  pub camera: CameraProjection,
+  pub fov: Option<f32>,
+  pub aperture: Aperture,
+  pub exposure: Option<Exposure>,
+  pub lens: Option<String>,
+  // The first and last frames to render, or None for a still image.
+  pub frames: Option<(u32, u32)>,
+  pub fps: f32,
+  pub shutter_angle: f32,
+  pub shutter_offset: f32,
+  pub shutter_interval: (f32, f32),
+  pub shutter_curve: ShutterCurve,
+  pub stereo: Option<StereoLayout>,
+  pub interpupillary_distance: f32,
+  pub crop: Option<CropWindow>,
+  pub crop_only: bool,
+  pub composite: bool,
+  // The images' file extension, which picks their format.
+  pub format: &'static str,
+  pub aovs: Vec<Aov>,
+  pub help: bool,
+}
+
+impl Default for Options {
+  fn default() -> Options {
+    Options {
+      scene: Scene::Spheres,
+      spectral: false,
+      samples_per_pixel: 100,
+      denoiser: None,
+      keep_noisy: false,
+      regularization: Regularization::default(),
+      post: PostEffects::default(),
+      caption: None,
+      texture_filter: Filter::Trilinear,
+      texture_wrap: WrapMode::Clamp,
+      ground_texture: None,
+      camera: CameraProjection::Perspective,
+      fov: None,
+      aperture: Aperture::Disk,
+      exposure: None,
+      lens: None,
+      frames: None,
+      fps: 24.0,
+      shutter_angle: 180.0,
+      shutter_offset: 0.0,
+      shutter_interval: (0.0, 1.0),
+      shutter_curve: ShutterCurve::Box,
+      stereo: None,
+      interpupillary_distance: 0.065,
+      crop: None,
+      crop_only: false,
+      composite: false,
+      format: "png",
+      aovs: Vec::new(),
+      help: false,
+    }
+  }
+}
+
+impl Options {
+  // Parses the command line, without the program name.
+  pub fn from_args<I: Iterator<Item = String>>(
+    args: I,
+  ) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args;
+    // Whether how long the shutter is open was given by its speed, or by
+    // its interval or angle.
+    let mut shutter_speed = false;
+    let mut shutter_timing = false;
+    while let Some(arg) = args.next() {
+      let mut value = || {
+        args
+          .next()
+          .ok_or_else(|| format!("Missing value for {}", arg))
+      };
+      match arg.as_str() {
+        "--scene" => {
+          options.scene = match value()?.as_str() {
+            "spheres" => Scene::Spheres,
+            "showcase" => Scene::Showcase,
+            other => return Err(format!("Unknown scene: {}", other)),
+          }
+        }
+        "--spectral" => options.spectral = true,
+        "--samples" => {
+          let samples = value()?;
+          options.samples_per_pixel = match samples.parse() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("Bad samples per pixel: {}", samples)),
+          };
+        }
+        "--denoise" => {
+          options.denoiser = Some(match value()?.as_str() {
+            "atrous" => Denoiser::ATrous,
+            "nlm" => Denoiser::NonLocalMeans,
+            other => return Err(format!("Unknown denoiser: {}", other)),
+          })
+        }
+        "--keep-noisy" => options.keep_noisy = true,
+        "--clamp-direct" | "--clamp-indirect" | "--reject-outliers" => {
+          let x = value()?;
+          let regularization = &mut options.regularization;
+          let setting = match arg.as_str() {
+            "--clamp-direct" => &mut regularization.clamp_direct,
+            "--clamp-indirect" => &mut regularization.clamp_indirect,
+            _ => &mut regularization.outlier_deviations,
+          };
+          *setting = Some(
+            parse_positive(&x)
+              .ok_or_else(|| format!("Bad value for {}: {}", arg, x))?,
+          );
+        }
+        "--regularize" => {
+          let x = value()?;
+          options.regularization.min_roughness = match x.parse() {
+            Ok(roughness) if (0.0..=1.0).contains(&roughness) => roughness,
+            _ => return Err(format!("Bad roughness: {}", x)),
+          };
+        }
+        "--bloom" | "--glare" => {
+          let x = value()?;
+          let bad = || format!("Bad value for {}: {}", arg, x);
+          let (strength, extra) = match x.split_once(':') {
+            Some((strength, extra)) => (strength, Some(extra)),
+            None => (x.as_str(), None),
+          };
+          let strength = parse_positive(strength).ok_or_else(bad)?;
+          let post = &mut options.post;
+          if arg == "--bloom" {
+            post.bloom = strength;
+            if let Some(radius) = extra {
+              post.bloom_radius = parse_positive(radius).ok_or_else(bad)?;
+            }
+          } else {
+            post.glare = strength;
+            if let Some(points) = extra {
+              post.glare_points = match points.parse() {
+                Ok(points) if points >= 2 => points,
+                _ => return Err(bad()),
+              };
+            }
+          }
+        }
+        "--highlight-threshold" | "--chromatic-aberration" | "--vignette" => {
+          let x = value()?;
+          let post = &mut options.post;
+          let (setting, max) = match arg.as_str() {
+            "--highlight-threshold" => (&mut post.threshold, f32::INFINITY),
+            "--chromatic-aberration" => {
+              (&mut post.chromatic_aberration, f32::INFINITY)
+            }
+            _ => (&mut post.vignette, 1.0),
+          };
+          *setting = parse_positive(&x)
+            .filter(|&x| x <= max)
+            .ok_or_else(|| format!("Bad value for {}: {}", arg, x))?;
+        }
+        "--caption" | "--caption-font" | "--caption-size"
+        | "--caption-color" | "--caption-position" => {
+          let x = value()?;
+          let bad = || format!("Bad value for {}: {}", arg, x);
+          let caption = options.caption.get_or_insert_with(Caption::default);
+          match arg.as_str() {
+            "--caption" => caption.text = x,
+            "--caption-font" => caption.font = x,
+            "--caption-size" => {
+              caption.size = parse_positive(&x).ok_or_else(bad)?
+            }
+            "--caption-color" => {
+              let rgb: Vec<f32> = x
+                .split(',')
+                .map(|c| c.trim().parse().map_err(|_| bad()))
+                .collect::<Result<_, _>>()?;
+              caption.color = match rgb[..] {
+                [r, g, b] if rgb.iter().all(|c| (0.0..=1.0).contains(c)) => {
+                  [r, g, b]
+                }
+                _ => return Err(bad()),
+              };
+            }
+            _ => {
+              caption.corner = match x.as_str() {
+                "top-left" => Corner::TopLeft,
+                "top-right" => Corner::TopRight,
+                "bottom-left" => Corner::BottomLeft,
+                "bottom-right" => Corner::BottomRight,
+                _ => return Err(bad()),
+              }
+            }
+          }
+        }
+        "--texture-filter" => {
+          options.texture_filter = match value()?.as_str() {
+            "nearest" => Filter::Nearest,
+            "bilinear" => Filter::Bilinear,
+            "trilinear" => Filter::Trilinear,
+            other => return Err(format!("Unknown texture filter: {}", other)),
+          }
+        }
+        "--texture-wrap" => {
+          options.texture_wrap = match value()?.as_str() {
+            "repeat" => WrapMode::Repeat,
+            "mirror" => WrapMode::Mirror,
+            "clamp" => WrapMode::Clamp,
+            other => {
+              return Err(format!("Unknown texture wrap mode: {}", other))
+            }
+          }
+        }
+        "--ground-texture" => options.ground_texture = Some(value()?),
+        "--camera" => {
+          options.camera = match value()?.as_str() {
+            "perspective" => CameraProjection::Perspective,
+            "orthographic" => CameraProjection::Orthographic,
+            "fisheye" => CameraProjection::Fisheye,
+            "equirectangular" => CameraProjection::Equirectangular,
+            other => return Err(format!("Unknown camera: {}", other)),
+          }
+        }
+        "--fov" => {
+          let fov = value()?;
+          options.fov = Some(
+            fov
+              .parse()
+              .map_err(|_| format!("Bad field of view: {}", fov))?,
+          );
+        }
+        "--bokeh" => options.aperture = parse_aperture(&value()?)?,
+        "--f-stop" | "--shutter" | "--iso" => {
+          let x = value()?;
+          let exposure = options.exposure.get_or_insert_with(Exposure::default);
+          let setting = match arg.as_str() {
+            "--f-stop" => &mut exposure.f_number,
+            "--shutter" => &mut exposure.shutter,
+            _ => &mut exposure.iso,
+          };
+          *setting = parse_positive(&x)
+            .ok_or_else(|| format!("Bad value for {}: {}", arg, x))?;
+          shutter_speed |= arg == "--shutter";
+        }
+        "--lens" => options.lens = Some(value()?),
+        "--frames" => {
+          let frames = value()?;
+          let bad = || format!("Bad frames: {}", frames);
+          options.frames = Some(match frames.split_once('-') {
+            Some((first, last)) => {
+              let first = first.parse().map_err(|_| bad())?;
+              let last = last.parse().map_err(|_| bad())?;
+              if last < first {
+                return Err(bad());
+              }
+              (first, last)
+            }
+            None => match frames.parse::<u32>() {
+              Ok(count) if count > 0 => (0, count - 1),
+              _ => return Err(bad()),
+            },
+          });
+        }
+        "--fps" | "--shutter-angle" => {
+          let x = value()?;
+          let setting = if arg == "--fps" {
+            &mut options.fps
+          } else {
+            shutter_timing = true;
+            &mut options.shutter_angle
+          };
+          *setting = parse_positive(&x)
+            .ok_or_else(|| format!("Bad value for {}: {}", arg, x))?;
+          // A shutter can't stay open for longer than the whole frame.
+          if arg == "--shutter-angle" && options.shutter_angle > 360.0 {
+            return Err(format!("Bad value for {}: {}", arg, x));
+          }
+        }
+        "--shutter-offset" => {
+          let x = value()?;
+          options.shutter_offset = x
+            .parse()
+            .map_err(|_| format!("Bad shutter offset: {}", x))?;
+        }
+        "--shutter-interval" => {
+          let x = value()?;
+          let bad = || format!("Bad shutter interval: {}", x);
+          let (open, close) = x.split_once(':').ok_or_else(bad)?;
+          let open: f32 = open.parse().map_err(|_| bad())?;
+          let close: f32 = close.parse().map_err(|_| bad())?;
+          if !open.is_finite() || !close.is_finite() || close <= open {
+            return Err(bad());
+          }
+          options.shutter_interval = (open, close);
+          shutter_timing = true;
+        }
+        "--shutter-curve" => {
+          let x = value()?;
+          options.shutter_curve = match x.as_str() {
+            "box" => ShutterCurve::Box,
+            "triangle" => ShutterCurve::Trapezoid { ramp: 0.5 },
+            _ => match x.strip_prefix("trapezoid:").map(|r| r.parse()) {
+              Some(Ok(ramp)) if ramp > 0.0 && ramp <= 0.5 => {
+                ShutterCurve::Trapezoid { ramp }
+              }
+              _ => return Err(format!("Unknown shutter curve: {}", x)),
+            },
+          }
+        }
+        "--stereo" => {
+          options.stereo = Some(match value()?.as_str() {
+            "side-by-side" => StereoLayout::SideBySide,
+            "top-bottom" => StereoLayout::TopBottom,
+            "separate" => StereoLayout::Separate,
+            other => return Err(format!("Unknown stereo layout: {}", other)),
+          })
+        }
+        "--ipd" => {
+          let ipd = value()?;
+          options.interpupillary_distance = ipd
+            .parse()
+            .map_err(|_| format!("Bad interpupillary distance: {}", ipd))?;
+        }
+        "--crop" => {
+          let crop = value()?;
+          let bounds = parse_bounds(&crop)
+            .filter(|b| b.iter().all(|x| (0.0..=1.0).contains(x)))
+            .ok_or_else(|| format!("Bad crop window: {}", crop))?;
+          options.crop = Some(CropWindow::Normalized(bounds));
+        }
+        "--pixel-bounds" => {
+          let bounds = value()?;
+          options.crop = Some(CropWindow::Pixels(
+            parse_bounds(&bounds)
+              .ok_or_else(|| format!("Bad pixel bounds: {}", bounds))?,
+          ));
+        }
+        "--crop-only" => options.crop_only = true,
+        "--composite" => options.composite = true,
+        "--format" => {
+          options.format = match value()?.as_str() {
+            "png" => "png",
+            "hdr" => "hdr",
+            "pfm" => "pfm",
+            other => return Err(format!("Unknown image format: {}", other)),
+          }
+        }
+        "--aov" => {
+          let names = value()?;
+          if names == "all" {
+            options.aovs = ALL_AOVS.to_vec();
+            continue;
+          }
+          for name in names.split(',') {
+            let aov = Aov::from_name(name.trim())
+              .ok_or_else(|| format!("Unknown AOV: {}", name))?;
+            if !options.aovs.contains(&aov) {
+              options.aovs.push(aov);
+            }
+          }
+        }
+        "--help" | "-h" => options.help = true,
+        _ => return Err(format!("Unknown option: {}", arg)),
+      }
+    }
+    if matches!(&options.caption, Some(c) if c.font.is_empty()) {
+      return Err("Captions need a font, from --caption-font".to_string());
+    }
+    if let (Some(exposure), true) = (options.exposure, shutter_speed) {
+      if shutter_timing {
+        return Err(
+          "--shutter can't be given with --shutter-interval or \
+           --shutter-angle"
+            .to_string(),
+        );
+      }
+      options.shutter_interval = (0.0, exposure.shutter);
+      options.shutter_angle = 360.0 * exposure.shutter * options.fps;
+      if options.frames.is_some() && options.shutter_angle > 360.0 {
+        return Err(format!(
+          "A shutter speed of {} s is longer than a frame at {} fps",
+          exposure.shutter, options.fps
+        ));
+      }
+    }
+    // Perspective and orthographic cameras size their view by the tangent of
+    // half the field of view, which runs off to infinity at 180 degrees.
+    if let Some(fov) = options.fov {
+      let wide = matches!(
+        options.camera,
+        CameraProjection::Fisheye | CameraProjection::Equirectangular
+      );
+      if fov >= 180.0 && !wide {
+        return Err(format!(
+          "A field of view of {} degrees needs a fisheye or \
+           equirectangular camera",
+          fov
+        ));
+      }
+    }
+    if options.crop_only && options.composite {
+      return Err("--crop-only images can't be composited".to_string());
+    }
+    // The effects would apply again to the image composited into, and
+    // stop at the crop window's edges.
+    if options.composite && !options.post.is_empty() {
+      return Err("Composited images can't have post effects".to_string());
+    }
+    Ok(options)
+  }
+
+  pub fn frame_range(&self) -> Option<FrameRange> {
+    self.frames.map(|(first, last)| FrameRange {
+      first,
+      last,
+      fps: self.fps,
+      shutter_angle: self.shutter_angle,
+      shutter_offset: self.shutter_offset,
+    })
+  }
+}
+
+fn parse_aperture(shape: &str) -> Result<Aperture, String> {
+  if shape == "disk" {
+    return Ok(Aperture::Disk);
+  }
+  if let Some(path) = shape.strip_prefix("image:") {
+    let image = image::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let image =
+      ApertureImage::new(&image).map_err(|e| format!("{}: {}", path, e))?;
+    return Ok(Aperture::Image(Arc::new(image)));
+  }
+  let bad = || format!("Unknown bokeh shape: {}", shape);
+  let blades = shape.strip_prefix("blades:").ok_or_else(bad)?;
+  let (blades, degrees) = match blades.split_once(':') {
+    Some((blades, degrees)) => (blades, degrees.parse().map_err(|_| bad())?),
+    None => (blades, 0.0_f32),
+  };
+  match blades.parse() {
+    Ok(blades) if blades >= 3 => Ok(Aperture::Polygon {
+      blades,
+      rotation: degrees.to_radians(),
+    }),
+    _ => Err(bad()),
+  }
+}
+
+// Four comma separated numbers, x0, x1, y0 and y1, with x0 < x1 and
+// y0 < y1.
+fn parse_bounds<X: std::str::FromStr + PartialOrd>(s: &str) -> Option<[X; 4]> {
+  let mut bounds = s.split(',').map(|x| x.trim().parse().ok());
+  let mut next = || bounds.next().flatten();
+  let [x0, x1, y0, y1] = [next()?, next()?, next()?, next()?];
+  if bounds.next().is_some() || x0 >= x1 || y0 >= y1 {
+    return None;
+  }
+  Some([x0, x1, y0, y1])
+}
+
+// A positive number, or a fraction such as 1/60.
+fn parse_positive(s: &str) -> Option<f32> {
+  let x = match s.split_once('/') {
+    Some((a, b)) => a.parse::<f32>().ok()? / b.parse::<f32>().ok()?,
+    None => s.parse().ok()?,
+  };
+  if x > 0.0 && x.is_finite() {
+    Some(x)
+  } else {
+    None
+  }
+}
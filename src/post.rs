@@ -0,0 +1,302 @@
+// Effects of a real camera's lens, applied to the film once it's rendered.
+// They work on linear light, before the image is tone mapped for saving, so
+// only what's really brighter than white blooms and glares.
+use crate::canvas::*;
+use crate::vec3::*;
+use std::f32::consts::PI;
+
+type T = f32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PostEffects {
+  // Pixels brighter than this, in luminance, bloom and glare, with the
+  // light they have beyond it.
+  pub threshold: T,
+  // How much of that light spreads into a soft glow, and how far, as the
+  // standard deviation of the widest of its blurs in image heights.
+  pub bloom: T,
+  pub bloom_radius: T,
+  // How much of it streaks out in a star of this many points, as from the
+  // blades of an aperture.
+  pub glare: T,
+  pub glare_points: u32,
+  // How many pixels apart red and blue are at the image's corners, where
+  // the lens focuses them at different sizes.
+  pub chromatic_aberration: T,
+  // How much darker the corners are than the center, from 0 to 1.
+  pub vignette: T,
+}
+
+impl Default for PostEffects {
+  fn default() -> PostEffects {
+    PostEffects {
+      threshold: 1.0,
+      bloom: 0.0,
+      bloom_radius: 0.02,
+      glare: 0.0,
+      glare_points: 6,
+      chromatic_aberration: 0.0,
+      vignette: 0.0,
+    }
+  }
+}
+
+// Where a view's whole image lies on its canvas, in pixels from the lower
+// left corner. A crop-only canvas holds just part of it.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageBounds {
+  pub x: T,
+  pub y: T,
+  pub width: T,
+  pub height: T,
+}
+
+// A region's pixels, in rows from the bottom.
+struct Layer {
+  width: usize,
+  height: usize,
+  pixels: Vec<Color>,
+}
+
+impl Layer {
+  fn get(&self, x: i64, y: i64) -> Option<Color> {
+    if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+      return None;
+    }
+    Some(self.pixels[y as usize * self.width + x as usize])
+  }
+
+  // Interpolates between the pixels around a point, given in pixels with
+  // their centers at half integers. Points outside take the nearest edge.
+  fn sample(&self, x: T, y: T) -> Color {
+    let x = (x - 0.5).clamp(0.0, (self.width - 1) as T);
+    let y = (y - 0.5).clamp(0.0, (self.height - 1) as T);
+    let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+    let (fx, fy) = (x - x0 as T, y - y0 as T);
+    let at = |x, y| self.get(x, y).unwrap_or(Color::new(0.0, 0.0, 0.0));
+    let below = (1.0 - fx) * at(x0, y0) + fx * at(x0 + 1, y0);
+    let above = (1.0 - fx) * at(x0, y0 + 1) + fx * at(x0 + 1, y0 + 1);
+    (1.0 - fy) * below + fy * above
+  }
+}
+
+impl PostEffects {
+  pub fn is_empty(&self) -> bool {
+    self.bloom <= 0.0
+      && self.glare <= 0.0
+      && self.chromatic_aberration <= 0.0
+      && self.vignette <= 0.0
+  }
+
+  // Applies the effects to a region of the image, which is all of or part
+  // of the image within bounds. Pixels outside it are left alone, and don't
+  // bloom or glare into it.
+  pub fn apply(
+    &self,
+    image: &mut Canvas,
+    region: PixelRect,
+    bounds: ImageBounds,
+  ) {
+    let mut layer = Layer {
+      width: region.width() as usize,
+      height: region.height() as usize,
+      pixels: Vec::with_capacity((region.width() * region.height()) as usize),
+    };
+    for y in region.y0..region.y1 {
+      for x in region.x0..region.x1 {
+        layer.pixels.push(image.get(x, y));
+      }
+    }
+    // The image's center, and how far each pixel is from it, in pixels.
+    let center = (
+      bounds.x + 0.5 * bounds.width - region.x0 as T,
+      bounds.y + 0.5 * bounds.height - region.y0 as T,
+    );
+    let half_diagonal = 0.5 * bounds.width.hypot(bounds.height);
+    let width = layer.width;
+    let offset = |k: usize| {
+      let (x, y) = ((k % width) as T, (k / width) as T);
+      ((x + 0.5 - center.0), (y + 0.5 - center.1))
+    };
+
+    if self.chromatic_aberration > 0.0 {
+      let scale = 0.5 * self.chromatic_aberration / half_diagonal;
+      let pixels = (0..layer.pixels.len())
+        .map(|k| {
+          let (x, y) = offset(k);
+          let channel = |s: T| layer.sample(center.0 + s * x, center.1 + s * y);
+          let (red, blue) = (channel(1.0 + scale), channel(1.0 - scale));
+          Color::new(red.r(), layer.pixels[k].g(), blue.b())
+        })
+        .collect();
+      layer.pixels = pixels;
+    }
+
+    if self.bloom > 0.0 || self.glare > 0.0 {
+      let highlights = Layer {
+        width: layer.width,
+        height: layer.height,
+        pixels: layer
+          .pixels
+          .iter()
+          .map(|&c| {
+            let luminance = c.luminance();
+            if luminance > self.threshold {
+              ((luminance - self.threshold) / luminance) * c
+            } else {
+              Color::new(0.0, 0.0, 0.0)
+            }
+          })
+          .collect(),
+      };
+      if self.bloom > 0.0 {
+        // Blurs that halve in width, so the glow has a bright core and a
+        // long tail.
+        const SCALES: i32 = 4;
+        let widest = self.bloom_radius * bounds.height;
+        for scale in 0..SCALES {
+          let sigma = widest / (1 << scale) as T;
+          let blurred = gaussian_blur(&highlights, sigma);
+          for (p, b) in layer.pixels.iter_mut().zip(blurred) {
+            *p += (self.bloom / SCALES as T) * b;
+          }
+        }
+      }
+      if self.glare > 0.0 {
+        let length = 0.1 * bounds.height;
+        let streaks = star(&highlights, self.glare_points, length);
+        for (p, s) in layer.pixels.iter_mut().zip(streaks) {
+          *p += self.glare * s;
+        }
+      }
+    }
+
+    if self.vignette > 0.0 {
+      for (k, p) in layer.pixels.iter_mut().enumerate() {
+        let (x, y) = offset(k);
+        let r2 = (x * x + y * y) / (half_diagonal * half_diagonal);
+        *p = (1.0 - self.vignette * r2).max(0.0) * *p;
+      }
+    }
+
+    for (k, p) in layer.pixels.iter().enumerate() {
+      let (x, y) = ((k % layer.width) as u32, (k / layer.width) as u32);
+      image.draw(region.x0 + x, region.y0 + y, p);
+    }
+  }
+}
+
+// Blurs a layer with a Gaussian of the given standard deviation in pixels,
+// one axis at a time. Near the edges it averages the pixels there are.
+fn gaussian_blur(layer: &Layer, sigma: T) -> Vec<Color> {
+  let radius = (3.0 * sigma).ceil().max(1.0) as i64;
+  let kernel: Vec<T> = (-radius..=radius)
+    .map(|x| (-(x * x) as T / (2.0 * sigma * sigma)).exp())
+    .collect();
+  let pass = |layer: &Layer, (dx, dy): (i64, i64)| {
+    let mut pixels = Vec::with_capacity(layer.pixels.len());
+    for y in 0..layer.height as i64 {
+      for x in 0..layer.width as i64 {
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        let mut total = 0.0;
+        for (t, w) in (-radius..=radius).zip(&kernel) {
+          if let Some(c) = layer.get(x + t * dx, y + t * dy) {
+            sum += *w * c;
+            total += w;
+          }
+        }
+        pixels.push((1.0 / total) * sum);
+      }
+    }
+    Layer {
+      width: layer.width,
+      height: layer.height,
+      pixels,
+    }
+  };
+  pass(&pass(layer, (1, 0)), (0, 1)).pixels
+}
+
+// Streaks a layer out into stars of the given number of points, each point
+// fading over about length pixels. Each pixel's streaks sum to its value.
+fn star(layer: &Layer, points: u32, length: T) -> Vec<Color> {
+  let steps = length.ceil().max(1.0) as i64 * 2;
+  let falloff: Vec<T> = (1..=steps)
+    .map(|t| (-2.0 * t as T / length).exp())
+    .collect();
+  let total = points as T * falloff.iter().sum::<T>();
+  // The first point is straight up.
+  let directions: Vec<(T, T)> = (0..points)
+    .map(|k| {
+      let angle = 0.5 * PI + 2.0 * PI * k as T / points as T;
+      (angle.cos(), angle.sin())
+    })
+    .collect();
+  let mut pixels = Vec::with_capacity(layer.pixels.len());
+  for y in 0..layer.height as i64 {
+    for x in 0..layer.width as i64 {
+      // The light streaking into this pixel comes from those behind it
+      // along each point.
+      let mut sum = Color::new(0.0, 0.0, 0.0);
+      for &(dx, dy) in &directions {
+        for (t, w) in (1..=steps).zip(&falloff) {
+          let sx = x - (t as T * dx).round() as i64;
+          let sy = y - (t as T * dy).round() as i64;
+          if let Some(c) = layer.get(sx, sy) {
+            sum += *w * c;
+          }
+        }
+      }
+      pixels.push((1.0 / total) * sum);
+    }
+  }
+  pixels
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_post_effects() {
+    let (w, h) = (40, 30);
+    let mut image = Canvas::new(w, h);
+    for y in 0..h {
+      for x in 0..w {
+        image.draw(x, y, &Color::new(0.5, 0.5, 0.5));
+      }
+    }
+    image.draw(20, 15, &Color::new(50.0, 50.0, 50.0));
+    let bounds = ImageBounds {
+      x: 0.0,
+      y: 0.0,
+      width: w as T,
+      height: h as T,
+    };
+    let region = CropWindow::Pixels([0, w, 0, h]).rect(w, h).unwrap();
+    let effects = PostEffects {
+      bloom: 0.2,
+      bloom_radius: 0.1,
+      glare: 0.2,
+      glare_points: 4,
+      vignette: 0.5,
+      ..PostEffects::default()
+    };
+    effects.apply(&mut image, region, bounds);
+    // The bright pixel glows onto its neighbors, more along the star's
+    // points than between them, and the corners darken.
+    let r = |x, y| image.get(x, y).r();
+    assert!(r(20, 18) > r(23, 18) && r(23, 18) > 0.5);
+    assert!(r(0, 0) < 0.3);
+    // Red and blue spread apart towards the edges.
+    let mut image = Canvas::new(w, h);
+    image.draw(30, 15, &Color::new(1.0, 1.0, 1.0));
+    let effects = PostEffects {
+      chromatic_aberration: 4.0,
+      ..PostEffects::default()
+    };
+    effects.apply(&mut image, region, bounds);
+    let c = image.get(30, 15);
+    assert!(c.g() == 1.0 && c.r() < 1.0 && c.b() < 1.0);
+  }
+}
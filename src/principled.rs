@@ -0,0 +1,296 @@
+// A principled "uber" material in the style of Burley's Disney BRDF, meant as
+// a common target for assets authored in other tools.
+//
+// The material is a stack of lobes: an optional clearcoat on top of either a
+// metal or a dielectric base, where the dielectric base is a specular layer
+// over a mix of diffuse (with sheen) and rough transmission. Every scatter
+// picks one lobe with probability proportional to its share of the energy,
+// so that Fresnel terms cancel out of the sample weights.
+use crate::material2::*;
+use crate::microfacet::*;
+use crate::object::*;
+use crate::ray::*;
+use crate::texture::*;
+use crate::thin_film::*;
+use crate::vec3::*;
+use rand::Rng;
+use std::f32::consts::PI;
+
+type T = f32;
+
+// All scalar parameters are in [0, 1], and read from the average of their
+// texture's channels.
+#[derive(Clone)]
+pub struct Principled {
+  pub base_color: Texture,
+  // 0 is a dielectric, 1 is a metal tinted by base_color.
+  pub metallic: Texture,
+  pub roughness: Texture,
+  // The dielectric's reflectance at normal incidence, scaled so that 0.5 is
+  // 4% (an index of refraction of 1.5). This also sets the index used for
+  // transmission.
+  pub specular: Texture,
+  // A grazing-angle, base-color-tinted sheen for cloth.
+  pub sheen: Texture,
+  pub clearcoat: Texture,
+  pub clearcoat_roughness: Texture,
+  // How much of the non-metallic base is transmitted instead of diffused.
+  pub transmission: Texture,
+  // How much of each channel transmitted light loses per unit of distance
+  // traveled inside, as in Material::Dielectric.
+  pub absorption: Color,
+}
+
+impl Principled {
+  // A rough plastic with the given base color. Other parameters can
+  // be overridden with struct update syntax.
+  pub fn new(base_color: Texture) -> Principled {
+    Principled {
+      base_color,
+      metallic: Texture::constant(0.0),
+      roughness: Texture::constant(0.5),
+      specular: Texture::constant(0.5),
+      sheen: Texture::constant(0.0),
+      clearcoat: Texture::constant(0.0),
+      clearcoat_roughness: Texture::constant(0.1),
+      transmission: Texture::constant(0.0),
+      absorption: Color::new(0.0, 0.0, 0.0),
+    }
+  }
+}
+
+// The index of refraction whose normal-incidence reflectance is
+// 0.08 * specular, as in the Disney BRDF.
+fn refraction_index_from_specular(specular: T) -> T {
+  refraction_index_from_reflectance(0.08 * specular.clamp(0.0, 1.0))
+}
+
+fn schlick_weight(cos_theta: T) -> T {
+  (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+pub fn scatter_principled(
+  params: &Principled,
+  min_roughness: T,
+  incident_ray: &Ray,
+  hit: &HitResultPayload,
+) -> Option<ScatterResult> {
+  let base_color = params.base_color.sample(hit);
+  let metallic = params.metallic.scalar_sample(hit).clamp(0.0, 1.0);
+  let roughness = params.roughness.scalar_sample(hit).max(min_roughness);
+  let refraction_index =
+    refraction_index_from_specular(params.specular.scalar_sample(hit));
+  let transmission = params.transmission.scalar_sample(hit).clamp(0.0, 1.0);
+
+  let frame = Frame::from_normal_and_tangent(hit.normal, hit.dpdu);
+  let wo = frame.to_local(-incident_ray.direction.normalize());
+  if wo.z() <= 0.0 {
+    return None;
+  }
+  let mut rng = rand::thread_rng();
+  let absorption = params.absorption;
+  let scattered = |wi: Vec3, attenuation: Color| -> Option<ScatterResult> {
+    let direction = frame.to_world(wi);
+    Some(ScatterResult {
+      attenuation,
+      scattered_ray: incident_ray.scatter_through(hit, direction, absorption),
+    })
+  };
+
+  // Inside a transmissive object the only way out is through the (rough)
+  // dielectric interface, so no other lobe applies.
+  if !hit.front_face {
+    if transmission <= 0.0 {
+      return None;
+    }
+    let distribution = TrowbridgeReitz::from_roughness(roughness, 0.0);
+    let (wi, g) =
+      sample_rough_dielectric(&distribution, wo, 1.0 / refraction_index)?;
+    // The base color already tinted the light on its way in.
+    let attenuation = unentered_transmittance(absorption, incident_ray, hit);
+    return scattered(wi, g * attenuation);
+  }
+
+  // The clearcoat is a colorless layer with a fixed index of 1.5 on top of
+  // everything else. Picking it with probability equal to the energy it
+  // reflects cancels the Fresnel term out of its weight.
+  let clearcoat = params.clearcoat.scalar_sample(hit).clamp(0.0, 1.0);
+  if clearcoat > 0.0 && clearcoat * fresnel_dielectric(wo.z(), 1.5) > rng.gen()
+  {
+    let roughness = params.clearcoat_roughness.scalar_sample(hit);
+    let distribution =
+      TrowbridgeReitz::from_roughness(roughness.max(min_roughness), 0.0);
+    let wm = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+    let wi = reflect(-wo, wm);
+    if wi.z() <= 0.0 {
+      return None;
+    }
+    let f =
+      fresnel_dielectric(wo.dot(wm), 1.5) / fresnel_dielectric(wo.z(), 1.5);
+    let g = f * distribution.g(wo, wi) / distribution.g1(wo);
+    return scattered(wi, Color::new(g, g, g));
+  }
+
+  let distribution = TrowbridgeReitz::from_roughness(roughness, 0.0);
+  if metallic > rng.gen() {
+    let wm = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+    let wi = reflect(-wo, wm);
+    if wi.z() <= 0.0 {
+      return None;
+    }
+    let f = fresnel_schlick(wo.dot(wm), base_color);
+    let g = distribution.g(wo, wi) / distribution.g1(wo);
+    return scattered(wi, g * f);
+  }
+
+  // The dielectric base. Its specular reflection is chosen with probability
+  // given by the Fresnel term; whatever is not reflected is either
+  // transmitted or diffused.
+  let wm = distribution.sample_visible_normal(wo, rng.gen(), rng.gen());
+  if fresnel_dielectric(wo.dot(wm), refraction_index) > rng.gen() {
+    let wi = reflect(-wo, wm);
+    if wi.z() <= 0.0 {
+      return None;
+    }
+    let g = distribution.g(wo, wi) / distribution.g1(wo);
+    return scattered(wi, Color::new(g, g, g));
+  }
+  if transmission > rng.gen() {
+    let wi = refract(-wo, wm, 1.0 / refraction_index);
+    if wi.z() >= 0.0 {
+      return None;
+    }
+    let g = distribution.g(wo, wi) / distribution.g1(wo);
+    return scattered(wi, g * base_color);
+  }
+
+  // Burley's retro-reflective diffuse plus sheen, sampled proportionally to
+  // the cosine so that the 1 / pi and the cosine cancel. The retro-reflection
+  // adds energy at grazing angles on rough surfaces, so the diffuse term is
+  // renormalized as in Lagarde and de Rousiers, "Moving Frostbite to
+  // Physically Based Rendering" (2014).
+  let mut wi = Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit();
+  if wi.near_zero() {
+    wi = Vec3::new(0.0, 0.0, 1.0);
+  }
+  let wi = wi.normalize();
+  let wh = (wi + wo).normalize();
+  let cos_theta_d = wi.dot(wh);
+  let energy_bias = 0.5 * roughness;
+  let energy_factor = 1.0 + (1.0 / 1.51 - 1.0) * roughness;
+  let fd90 = energy_bias + 2.0 * roughness * cos_theta_d * cos_theta_d;
+  let fd = energy_factor
+    * (1.0 + (fd90 - 1.0) * schlick_weight(wi.z()))
+    * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z()));
+  let sheen =
+    params.sheen.scalar_sample(hit) * PI * schlick_weight(cos_theta_d);
+  scattered(wi, fd * base_color + sheen * sheen_tint(base_color))
+}
+
+// The base color normalized to unit luminance, so sheen only borrows its hue.
+fn sheen_tint(base_color: Color) -> Color {
+  let luminance =
+    0.3 * base_color.r() + 0.6 * base_color.g() + 0.1 * base_color.b();
+  if luminance > 0.0 {
+    (1.0 / luminance) * base_color
+  } else {
+    Color::new(1.0, 1.0, 1.0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The average weight of each channel of a material's scattered rays,
+  // missing ones counting as black, with the standard error of the average,
+  // for light arriving along the direction onto a quad facing up.
+  fn average_weight(material: Material, direction: Vec3) -> [(T, T); 3] {
+    let quad = Quad::new(
+      Point::new(-1.0, -1.0, 0.0),
+      Vec3::new(2.0, 0.0, 0.0),
+      Vec3::new(0.0, 2.0, 0.0),
+      material,
+    );
+    let ray = Ray {
+      origin: Point(-direction),
+      direction,
+      time: 0.0,
+      wavelength: None,
+      cone: RayCone {
+        width: 0.0,
+        spread: 0.0,
+      },
+      media: Media::none(),
+    };
+    let hit = quad.hit(0.001, 10.0, &ray).unwrap();
+    let payload = quad.hit_payload(hit.t, &ray);
+    let n = 100_000;
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    let mut sum_of_squares = Color::new(0.0, 0.0, 0.0);
+    for _ in 0..n {
+      if let Some(sr) = payload.material.scatter(&ray, &payload, 0.0) {
+        sum += sr.attenuation;
+        sum_of_squares += sr.attenuation * sr.attenuation;
+      }
+    }
+    let n = n as T;
+    let channel = |sum: T, sum_of_squares: T| {
+      let mean = sum / n;
+      let variance = (sum_of_squares / n - mean * mean).max(0.0);
+      (mean, (variance / n).sqrt())
+    };
+    [
+      channel(sum.r(), sum_of_squares.r()),
+      channel(sum.g(), sum_of_squares.g()),
+      channel(sum.b(), sum_of_squares.b()),
+    ]
+  }
+
+  #[test]
+  fn test_white_furnace() {
+    // A white dielectric reflects and diffuses no more light than arrives, at
+    // any angle and roughness, allowing for averages straying by a few
+    // standard errors. The renormalized diffuse gives up some light to do
+    // so, most on rough surfaces seen head on, but not most of it.
+    for &roughness in &[0.0, 0.5, 1.0] {
+      let white = Material::new_principled(Principled {
+        roughness: Texture::constant(roughness),
+        ..Principled::new(Texture::constant(1.0))
+      });
+      for &cos_theta in &[1.0, 0.7, 0.3, 0.1] {
+        let sin_theta = (1.0 - cos_theta * cos_theta as T).sqrt();
+        let direction = Vec3::new(sin_theta, 0.0, -cos_theta);
+        let (w, error) = average_weight(white.clone(), direction)[0];
+        assert!(
+          w - 5.0 * error <= 1.0 && w > 0.6,
+          "{} ± {} at roughness {} and cos {}",
+          w,
+          error,
+          roughness,
+          cos_theta
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn test_metallic_matches_conductor() {
+    let color = Color::new(0.9, 0.6, 0.3);
+    let roughness = Texture::constant(0.3);
+    let metal = Material::new_principled(Principled {
+      metallic: Texture::constant(1.0),
+      roughness: roughness.clone(),
+      ..Principled::new(Texture::Color(color))
+    });
+    let conductor =
+      Material::new_conductor(Texture::Color(color), roughness, 0.0);
+    let down = Vec3::new(0.0, 0.0, -1.0);
+    let a = average_weight(metal, down);
+    let b = average_weight(conductor, down);
+    for (&(a, a_error), &(b, b_error)) in a.iter().zip(&b) {
+      let error = (a_error * a_error + b_error * b_error).sqrt();
+      assert!((a - b).abs() <= 5.0 * error + 1e-4, "{} != {}", a, b);
+    }
+  }
+}
@@ -0,0 +1,131 @@
+// Unit quaternions, for rotations that interpolate smoothly.
+use crate::animation::*;
+use crate::vec3::*;
+
+type T = f32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quaternion {
+  w: T,
+  v: Vec3,
+}
+
+impl Quaternion {
+  pub fn identity() -> Quaternion {
+    Quaternion {
+      w: 1.0,
+      v: Vec3::new(0.0, 0.0, 0.0),
+    }
+  }
+
+  // A counterclockwise rotation by angle radians about axis.
+  pub fn from_axis_angle(axis: Vec3, angle: T) -> Quaternion {
+    let (sin, cos) = (0.5 * angle).sin_cos();
+    Quaternion {
+      w: cos,
+      v: sin * axis.normalize(),
+    }
+  }
+
+  pub fn conjugate(self) -> Quaternion {
+    Quaternion {
+      w: self.w,
+      v: -self.v,
+    }
+  }
+
+  pub fn rotate(self, x: Vec3) -> Vec3 {
+    // q x q*, expanded.
+    let t = 2.0 * self.v.cross(x);
+    x + self.w * t + self.v.cross(t)
+  }
+
+  // The angle of the rotation that takes one to the other.
+  pub fn angle_to(self, other: Quaternion) -> T {
+    2.0 * self.dot(other).abs().min(1.0).acos()
+  }
+
+  fn dot(self, other: Quaternion) -> T {
+    self.w * other.w + self.v.dot(other.v)
+  }
+
+  fn scaled_sum(terms: &[(T, Quaternion)]) -> Quaternion {
+    let mut sum = Quaternion {
+      w: 0.0,
+      v: Vec3::new(0.0, 0.0, 0.0),
+    };
+    for &(weight, q) in terms {
+      sum.w += weight * q.w;
+      sum.v += weight * q.v;
+    }
+    sum
+  }
+
+  fn normalize(self) -> Quaternion {
+    let norm = self.dot(self).sqrt();
+    Quaternion {
+      w: self.w / norm,
+      v: self.v / norm,
+    }
+  }
+
+  // Turns from a to b at a constant rate, the short way around.
+  pub fn slerp(a: Quaternion, b: Quaternion, s: T) -> Quaternion {
+    let mut cos = a.dot(b);
+    // q and -q are the same rotation.
+    let b = if cos < 0.0 {
+      cos = -cos;
+      Quaternion::scaled_sum(&[(-1.0, b)])
+    } else {
+      b
+    };
+    if cos > 0.9995 {
+      return Quaternion::scaled_sum(&[(1.0 - s, a), (s, b)]).normalize();
+    }
+    let theta = cos.acos();
+    let sin = theta.sin();
+    Quaternion::scaled_sum(&[
+      (((1.0 - s) * theta).sin() / sin, a),
+      ((s * theta).sin() / sin, b),
+    ])
+  }
+}
+
+impl Animatable for Quaternion {
+  // Normalized, with each term flipped to the first's side, which is close
+  // to the spherical average for the nearby rotations of Bezier handles.
+  fn weighted_sum(terms: &[(T, Quaternion)]) -> Quaternion {
+    let first = terms[0].1;
+    let aligned: Vec<_> = terms
+      .iter()
+      .map(|&(w, q)| if q.dot(first) < 0.0 { (-w, q) } else { (w, q) })
+      .collect();
+    Quaternion::scaled_sum(&aligned).normalize()
+  }
+
+  fn lerp(a: Quaternion, b: Quaternion, s: T) -> Quaternion {
+    Quaternion::slerp(a, b, s)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::f32::consts::PI;
+
+  #[test]
+  fn test_rotation_and_slerp() {
+    let y = Vec3::new(0.0, 1.0, 0.0);
+    let x = Vec3::new(1.0, 0.0, 0.0);
+    let quarter = Quaternion::from_axis_angle(y, 0.5 * PI);
+    // Counterclockwise about y, seen from above, takes x to -z.
+    let r = quarter.rotate(x);
+    assert!((r - Vec3::new(0.0, 0.0, -1.0)).norm() < 1e-6);
+    assert!((quarter.conjugate().rotate(r) - x).norm() < 1e-6);
+
+    // Halfway between no turn and a quarter turn is an eighth of a turn.
+    let eighth = Quaternion::slerp(Quaternion::identity(), quarter, 0.5);
+    let expected = Quaternion::from_axis_angle(y, 0.25 * PI);
+    assert!((eighth.rotate(x) - expected.rotate(x)).norm() < 1e-6);
+  }
+}
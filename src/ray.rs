@@ -1,11 +1,89 @@
+use crate::object::*;
 use crate::vec3::*;
 
 type T = f32;
 
+// Tracks how wide a pixel's footprint becomes as a ray travels, so textures
+// can be filtered over the area a sample covers. See Akenine-Moller et al.,
+// "Texture Level of Detail Strategies for Real-Time Ray Tracing" (2019).
+#[derive(Clone, Copy, Debug)]
+pub struct RayCone {
+  // The footprint's width at the ray's origin.
+  pub width: T,
+  // How fast the footprint grows per unit of distance traveled.
+  pub spread: T,
+}
+
+impl RayCone {
+  pub fn width_at(&self, distance: T) -> T {
+    self.width + self.spread * distance
+  }
+}
+
+// How many nested media a ray keeps track of. Entering more than this
+// replaces the innermost one.
+const MAX_MEDIA: usize = 4;
+
+// The absorbing media a ray travels through, innermost last, so that a ray
+// leaving a nested object carries on through the one around it.
+#[derive(Clone, Copy)]
+pub struct Media {
+  absorption: [Color; MAX_MEDIA],
+  depth: usize,
+}
+
+impl Media {
+  pub fn none() -> Media {
+    Media {
+      absorption: [Color::new(0.0, 0.0, 0.0); MAX_MEDIA],
+      depth: 0,
+    }
+  }
+  pub fn is_empty(&self) -> bool {
+    self.depth == 0
+  }
+  pub fn entered(&self, absorption: Color) -> Media {
+    let mut media = *self;
+    media.absorption[self.depth.min(MAX_MEDIA - 1)] = absorption;
+    media.depth = (self.depth + 1).min(MAX_MEDIA);
+    media
+  }
+  pub fn exited(&self) -> Media {
+    Media {
+      depth: self.depth.saturating_sub(1),
+      ..*self
+    }
+  }
+  // The fraction of each channel left after traveling the distance through
+  // the innermost medium.
+  pub fn transmittance(&self, distance: T) -> Color {
+    if self.is_empty() {
+      return Color::new(1.0, 1.0, 1.0);
+    }
+    transmittance(self.absorption[self.depth - 1], distance)
+  }
+}
+
+// The fraction of each channel left after traveling the distance through a
+// medium with the given absorption per unit of distance.
+pub fn transmittance(absorption: Color, distance: T) -> Color {
+  Color::new(
+    (-absorption.r() * distance).exp(),
+    (-absorption.g() * distance).exp(),
+    (-absorption.b() * distance).exp(),
+  )
+}
+
 pub struct Ray {
   pub origin: Point,
   pub direction: Vec3,
-  pub time: T
+  pub time: T,
+  // The hero wavelength, in nanometers, when rendering spectrally. RGB
+  // renders leave this unset.
+  pub wavelength: Option<T>,
+  pub cone: RayCone,
+  // The media the ray is inside of. Camera rays start outside of all of them.
+  pub media: Media,
 }
 
 impl Ray {
@@ -15,4 +93,40 @@ impl Ray {
   pub fn at(&self, t: T) -> Point {
     Point(self.origin.0 + t * self.direction)
   }
+  // The ray leaving the surface this ray hit, in the given direction. It
+  // carries on with this ray's time, wavelength, footprint and media.
+  pub fn scatter(&self, hit: &HitResultPayload, direction: Vec3) -> Ray {
+    Ray {
+      origin: hit.p,
+      direction,
+      time: self.time,
+      wavelength: self.wavelength,
+      cone: RayCone {
+        width: hit.footprint,
+        spread: self.cone.spread,
+      },
+      media: self.media,
+    }
+  }
+  // Like scatter, but off a surface bounding a medium with the given
+  // absorption. A direction through the surface enters the medium at a
+  // front face, and leaves it at a back face.
+  pub fn scatter_through(
+    &self,
+    hit: &HitResultPayload,
+    direction: Vec3,
+    absorption: Color,
+  ) -> Ray {
+    let media = if direction.dot(hit.normal) >= 0.0 {
+      self.media
+    } else if hit.front_face {
+      self.media.entered(absorption)
+    } else {
+      self.media.exited()
+    };
+    Ray {
+      media,
+      ..self.scatter(hit, direction)
+    }
+  }
 }
@@ -0,0 +1,102 @@
+// Ways of trading a little bias for much less noise from rare, bright paths,
+// like caustics through glass seen on a diffuse floor. Left alone they show
+// up as fireflies: lone bright pixels that take far more samples to average
+// out than the rest of the image.
+use crate::vec3::*;
+
+type T = f32;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Regularization {
+  // The brightest a sample may be, in luminance, for light reaching the
+  // camera after at most one bounce, and after more. Brighter samples are
+  // scaled down, keeping their hue.
+  pub clamp_direct: Option<T>,
+  pub clamp_indirect: Option<T>,
+  // How rough surfaces are at least after the first bounce.
+  pub min_roughness: T,
+  // Samples brighter than the rest of their pixel's by this many standard
+  // deviations are dropped.
+  pub outlier_deviations: Option<T>,
+}
+
+impl Regularization {
+  // How rough surfaces are at least for a ray that has already bounced
+  // this many times. Surfaces seen directly keep their own roughness.
+  pub fn min_roughness(&self, bounces: u32) -> T {
+    if bounces > 0 {
+      self.min_roughness
+    } else {
+      0.0
+    }
+  }
+
+  // Clamps a sample whose light bounced this many times on the way.
+  pub fn clamp(&self, sample: Color, bounces: u32) -> Color {
+    let limit = if bounces <= 1 {
+      self.clamp_direct
+    } else {
+      self.clamp_indirect
+    };
+    let luminance = sample.luminance();
+    match limit {
+      Some(limit) if luminance > limit => (limit / luminance) * sample,
+      _ => sample,
+    }
+  }
+
+  // Drops a pixel's outlying samples, comparing each with the mean and
+  // standard deviation of the others, so that one firefly can't hide
+  // itself by raising them.
+  pub fn reject_outliers(&self, samples: &mut Vec<Color>) {
+    let deviations = match self.outlier_deviations {
+      Some(deviations) if samples.len() > 2 => deviations,
+      _ => return,
+    };
+    let luminances: Vec<T> = samples.iter().map(|s| s.luminance()).collect();
+    let sum: T = luminances.iter().sum();
+    let sum_squared: T = luminances.iter().map(|l| l * l).sum();
+    let others = (samples.len() - 1) as T;
+    let mut luminances = luminances.into_iter();
+    samples.retain(|_| {
+      let l = luminances.next().unwrap();
+      let mean = (sum - l) / others;
+      let variance = ((sum_squared - l * l) / others - mean * mean).max(0.0);
+      l <= mean + deviations * variance.sqrt()
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_regularization() {
+    let regularization = Regularization {
+      clamp_direct: Some(4.0),
+      clamp_indirect: Some(1.0),
+      min_roughness: 0.3,
+      outlier_deviations: Some(3.0),
+    };
+    let bright = Color::new(8.0, 8.0, 8.0);
+    assert!((regularization.clamp(bright, 1).luminance() - 4.0).abs() < 1e-4);
+    assert!((regularization.clamp(bright, 2).luminance() - 1.0).abs() < 1e-4);
+    let dim = Color::new(0.5, 0.2, 0.1);
+    assert_eq!(regularization.clamp(dim, 3).r(), dim.r());
+    assert_eq!(regularization.min_roughness(0), 0.0);
+    assert_eq!(regularization.min_roughness(1), 0.3);
+
+    // A firefly among noisy samples goes, and only it.
+    let mut samples: Vec<_> = (0..32)
+      .map(|k| {
+        let x = 0.4 + 0.1 * (k % 3) as T;
+        Color::new(x, x, x)
+      })
+      .collect();
+    samples.push(Color::new(50.0, 50.0, 50.0));
+    regularization.reject_outliers(&mut samples);
+    assert_eq!(samples.len(), 32);
+    assert!(samples.iter().all(|s| s.r() < 1.0));
+  }
+}
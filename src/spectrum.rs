@@ -0,0 +1,355 @@
+// Spectral rendering support: sampling wavelengths, lifting RGB colors to
+// spectra, and projecting spectra back to RGB.
+//
+// Each path carries N_WAVELENGTHS wavelengths at once, using hero wavelength
+// sampling (Wilkie et al., "Hero Wavelength Spectral Sampling", 2014): the
+// first, "hero", wavelength is importance sampled, and the others are evenly
+// rotated away from it. When a path goes through something whose behaviour
+// depends on the wavelength, such as a dispersive dielectric, only the hero
+// wavelength survives.
+use crate::vec3::*;
+use std::ops::{Add, AddAssign, Div, Mul, MulAssign};
+use std::sync::OnceLock;
+
+type T = f32;
+
+pub const LAMBDA_MIN: T = 360.0;
+pub const LAMBDA_MAX: T = 830.0;
+pub const N_WAVELENGTHS: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SampledWavelengths {
+  lambda: [T; N_WAVELENGTHS],
+  pdf: [T; N_WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+  // Samples wavelengths proportionally to an approximation of the visual
+  // response, from a uniform u in [0, 1). This is the distribution used by
+  // pbrt-v4.
+  pub fn sample_visible(u: T) -> SampledWavelengths {
+    let mut lambda = [0.0; N_WAVELENGTHS];
+    let mut pdf = [0.0; N_WAVELENGTHS];
+    for i in 0..N_WAVELENGTHS {
+      let ui = (u + i as T / N_WAVELENGTHS as T).fract();
+      lambda[i] = 538.0 - 138.888_89 * (0.856_910_6 - 1.827_502 * ui).atanh();
+      pdf[i] = visible_wavelengths_pdf(lambda[i]);
+    }
+    SampledWavelengths { lambda, pdf }
+  }
+
+  pub fn hero(&self) -> T {
+    self.lambda[0]
+  }
+
+  // Drops every wavelength but the hero one. Its pdf is scaled so that
+  // averaging over all wavelengths still gives an unbiased estimate.
+  pub fn terminate_secondary(&mut self) {
+    if self.secondary_terminated() {
+      return;
+    }
+    for i in 1..N_WAVELENGTHS {
+      self.pdf[i] = 0.0;
+    }
+    self.pdf[0] /= N_WAVELENGTHS as T;
+  }
+
+  pub fn secondary_terminated(&self) -> bool {
+    self.pdf[1..].iter().all(|&p| p == 0.0)
+  }
+}
+
+fn visible_wavelengths_pdf(lambda: T) -> T {
+  if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+    return 0.0;
+  }
+  0.003_939_804 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+// A spectral quantity, evaluated at the wavelengths of a SampledWavelengths.
+#[derive(Clone, Copy, Debug)]
+pub struct SampledSpectrum(pub [T; N_WAVELENGTHS]);
+
+impl SampledSpectrum {
+  pub fn constant(x: T) -> SampledSpectrum {
+    SampledSpectrum([x; N_WAVELENGTHS])
+  }
+
+  // Lifts a linear RGB color to a spectrum with lift_rgb, and evaluates it at
+  // the given wavelengths.
+  pub fn from_rgb(
+    c: Color,
+    wavelengths: &SampledWavelengths,
+  ) -> SampledSpectrum {
+    let mut s = [0.0; N_WAVELENGTHS];
+    for (i, si) in s.iter_mut().enumerate() {
+      *si = lift_rgb(c, wavelengths.lambda[i]);
+    }
+    SampledSpectrum(s)
+  }
+
+  // Monte Carlo estimate of the linear sRGB color of this spectrum, given the
+  // wavelengths it was sampled at. The estimate is white balanced so that a
+  // constant spectrum of one maps to white.
+  pub fn to_rgb(self, wavelengths: &SampledWavelengths) -> Color {
+    let mut rgb = [0.0; 3];
+    for i in 0..N_WAVELENGTHS {
+      let pdf = wavelengths.pdf[i];
+      if pdf == 0.0 {
+        continue;
+      }
+      let response = rgb_response(wavelengths.lambda[i]);
+      for j in 0..3 {
+        rgb[j] += response[j] * self.0[i] / pdf;
+      }
+    }
+    let n = N_WAVELENGTHS as T;
+    Color::new(rgb[0] / n, rgb[1] / n, rgb[2] / n)
+  }
+}
+
+impl Add for SampledSpectrum {
+  type Output = SampledSpectrum;
+  fn add(self, rhs: SampledSpectrum) -> SampledSpectrum {
+    let mut s = self.0;
+    for (a, b) in s.iter_mut().zip(rhs.0.iter()) {
+      *a += b;
+    }
+    SampledSpectrum(s)
+  }
+}
+
+impl AddAssign for SampledSpectrum {
+  fn add_assign(&mut self, rhs: SampledSpectrum) {
+    *self = *self + rhs;
+  }
+}
+
+impl Mul for SampledSpectrum {
+  type Output = SampledSpectrum;
+  fn mul(self, rhs: SampledSpectrum) -> SampledSpectrum {
+    let mut s = self.0;
+    for (a, b) in s.iter_mut().zip(rhs.0.iter()) {
+      *a *= b;
+    }
+    SampledSpectrum(s)
+  }
+}
+
+impl MulAssign for SampledSpectrum {
+  fn mul_assign(&mut self, rhs: SampledSpectrum) {
+    *self = *self * rhs;
+  }
+}
+
+impl Div<T> for SampledSpectrum {
+  type Output = SampledSpectrum;
+  fn div(self, rhs: T) -> SampledSpectrum {
+    let mut s = self.0;
+    for a in s.iter_mut() {
+      *a /= rhs;
+    }
+    SampledSpectrum(s)
+  }
+}
+
+// Lifts a linear RGB color to a smooth spectrum that projects back to (nearly)
+// the same color, and evaluates it at the given wavelength.
+//
+// The spectrum is a combination of three fixed, smooth basis spectra that add
+// up to one, so gray colors become flat spectra. The combination is solved for
+// so that the round trip is exact, except for very saturated colors, where the
+// spectrum would have to go negative and is clamped.
+pub fn lift_rgb(c: Color, wavelength: T) -> T {
+  let m = &tables().rgb_to_basis;
+  let rgb = [c.r(), c.g(), c.b()];
+  let basis = basis_spectra(wavelength);
+  let mut value = 0.0;
+  for j in 0..3 {
+    let weight: T = (0..3).map(|k| m[j][k] * rgb[k]).sum();
+    value += weight * basis[j];
+  }
+  value.max(0.0)
+}
+
+// A piecewise Gaussian, with a different width on each side of its mean.
+fn piecewise_gaussian(lambda: T, mu: T, sigma_lo: T, sigma_hi: T) -> T {
+  let sigma = if lambda < mu { sigma_lo } else { sigma_hi };
+  let t = (lambda - mu) / sigma;
+  (-0.5 * t * t).exp()
+}
+
+// The CIE 1931 color matching functions, using the multi-lobe fit from Wyman
+// et al., "Simple Analytic Approximations to the CIE XYZ Color Matching
+// Functions" (2013).
+fn cie_xyz(lambda: T) -> [T; 3] {
+  let g = piecewise_gaussian;
+  [
+    1.056 * g(lambda, 599.8, 37.9, 31.0) + 0.362 * g(lambda, 442.0, 16.0, 26.7)
+      - 0.065 * g(lambda, 501.1, 20.4, 26.2),
+    0.821 * g(lambda, 568.8, 46.9, 40.5) + 0.286 * g(lambda, 530.9, 16.3, 31.1),
+    1.217 * g(lambda, 437.0, 11.8, 36.0) + 0.681 * g(lambda, 459.0, 26.0, 13.8),
+  ]
+}
+
+const XYZ_TO_LINEAR_SRGB: [[T; 3]; 3] = [
+  [3.240_454_2, -1.537_138_5, -0.498_531_4],
+  [-0.969_266, 1.876_010_8, 0.041_556],
+  [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+
+// The linear sRGB response to each wavelength, before white balancing.
+fn unbalanced_rgb_response(lambda: T) -> [T; 3] {
+  let xyz = cie_xyz(lambda);
+  let mut rgb = [0.0; 3];
+  for (j, c) in rgb.iter_mut().enumerate() {
+    *c = (0..3).map(|k| XYZ_TO_LINEAR_SRGB[j][k] * xyz[k]).sum();
+  }
+  rgb
+}
+
+fn rgb_response(lambda: T) -> [T; 3] {
+  let r = unbalanced_rgb_response(lambda);
+  let w = &tables().white_balance;
+  [r[0] * w[0], r[1] * w[1], r[2] * w[2]]
+}
+
+// Blue, green and red basis spectra for lifting RGB colors. They are smooth
+// and add up to one at every wavelength.
+fn basis_spectra(lambda: T) -> [T; 3] {
+  let sigmoid = |x: T| 1.0 / (1.0 + (-x).exp());
+  let blue = sigmoid((490.0 - lambda) / 15.0);
+  let red = sigmoid((lambda - 590.0) / 15.0);
+  [red, 1.0 - red - blue, blue]
+}
+
+struct Tables {
+  white_balance: [T; 3],
+  rgb_to_basis: [[T; 3]; 3],
+}
+
+fn tables() -> &'static Tables {
+  static TABLES: OnceLock<Tables> = OnceLock::new();
+  TABLES.get_or_init(|| {
+    // Integrate with the midpoint rule, one nanometer at a time.
+    let n = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    let lambdas = (0..n).map(|i| LAMBDA_MIN + i as T + 0.5);
+
+    let mut white = [0.0; 3];
+    for lambda in lambdas.clone() {
+      let r = unbalanced_rgb_response(lambda);
+      for j in 0..3 {
+        white[j] += r[j];
+      }
+    }
+    let white_balance = [1.0 / white[0], 1.0 / white[1], 1.0 / white[2]];
+
+    // basis_to_rgb[j][k] is channel j of basis spectrum k.
+    let mut basis_to_rgb = [[0.0; 3]; 3];
+    for lambda in lambdas {
+      let r = unbalanced_rgb_response(lambda);
+      let b = basis_spectra(lambda);
+      for j in 0..3 {
+        for k in 0..3 {
+          basis_to_rgb[j][k] += white_balance[j] * r[j] * b[k];
+        }
+      }
+    }
+    Tables {
+      white_balance,
+      rgb_to_basis: invert(&basis_to_rgb),
+    }
+  })
+}
+
+fn invert(m: &[[T; 3]; 3]) -> [[T; 3]; 3] {
+  let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+    m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+  };
+  let det = m[0][0] * cofactor(1, 2, 1, 2) - m[0][1] * cofactor(1, 2, 0, 2)
+    + m[0][2] * cofactor(1, 2, 0, 1);
+  [
+    [
+      cofactor(1, 2, 1, 2) / det,
+      -cofactor(0, 2, 1, 2) / det,
+      cofactor(0, 1, 1, 2) / det,
+    ],
+    [
+      -cofactor(1, 2, 0, 2) / det,
+      cofactor(0, 2, 0, 2) / det,
+      -cofactor(0, 1, 0, 2) / det,
+    ],
+    [
+      cofactor(1, 2, 0, 1) / det,
+      -cofactor(0, 2, 0, 1) / det,
+      cofactor(0, 1, 0, 1) / det,
+    ],
+  ]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Projects the lift of c back to RGB, averaging over many stratified
+  // wavelength samples.
+  fn round_trip(c: Color) -> Color {
+    let n = 4096;
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+      let wavelengths =
+        SampledWavelengths::sample_visible((i as T + 0.5) / n as T);
+      sum += SampledSpectrum::from_rgb(c, &wavelengths).to_rgb(&wavelengths);
+    }
+    sum /= n as T;
+    sum
+  }
+
+  fn assert_close(a: Color, b: Color) {
+    let d = (a - b).0.abs().max_element();
+    assert!(
+      d < 1e-2,
+      "({}, {}, {}) != ({}, {}, {})",
+      a.r(),
+      a.g(),
+      a.b(),
+      b.r(),
+      b.g(),
+      b.b()
+    );
+  }
+
+  #[test]
+  fn test_white_round_trips() {
+    assert_close(
+      round_trip(Color::new(1.0, 1.0, 1.0)),
+      Color::new(1.0, 1.0, 1.0),
+    );
+  }
+
+  #[test]
+  fn test_colors_round_trip() {
+    for c in &[
+      Color::new(0.5, 0.7, 1.0),
+      Color::new(0.7, 0.6, 0.5),
+      Color::new(0.2, 0.3, 0.1),
+    ] {
+      assert_close(round_trip(*c), *c);
+    }
+  }
+
+  #[test]
+  fn test_terminating_secondary_keeps_the_estimate() {
+    let n = 4096;
+    let c = Color::new(0.3, 0.6, 0.9);
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+      let mut wavelengths =
+        SampledWavelengths::sample_visible((i as T + 0.5) / n as T);
+      wavelengths.terminate_secondary();
+      sum += SampledSpectrum::from_rgb(c, &wavelengths).to_rgb(&wavelengths);
+    }
+    sum /= n as T;
+    assert_close(sum, c);
+  }
+}
@@ -1,61 +1,209 @@
-extern crate image;
-use image::{RgbImage, Rgb, open};
 use std::sync::Arc;
+use crate::image_texture::*;
+use crate::mapping::*;
+use crate::noise::*;
+use crate::object::*;
 use crate::vec3::*;
 
 type T = f32;
 
+// Where a texture is looked up.
+#[derive(Clone, Copy)]
+pub struct TexCoord {
+  // Surface coordinates, and how much of the unit square of them the lookup
+  // covers along each axis, which image textures filter over. Zero is a
+  // point lookup.
+  pub u: T,
+  pub v: T,
+  pub du: T,
+  pub dv: T,
+  // The hit point, in world space and relative to the object hit.
+  pub p: Point,
+  pub object_p: Point,
+  pub normal: Vec3,
+  // The width of the lookup's footprint, in world units.
+  pub footprint: T,
+}
+
+impl TexCoord {
+  pub fn from_hit(hit: &HitResultPayload) -> TexCoord {
+    let (du, dv) = hit.uv_footprint();
+    TexCoord {
+      u: hit.u,
+      v: hit.v,
+      du,
+      dv,
+      p: hit.p,
+      object_p: hit.object_p,
+      normal: hit.normal,
+      footprint: hit.footprint,
+    }
+  }
+}
+
 #[derive(Clone)]
 pub enum Texture {
   Color(Color),
-  Image(Arc<RgbImage>),
-  Checkers(Arc<Texture>, Arc<Texture>)
+  Image(Arc<ImageTexture>),
+  // A 3D checkerboard of cubes of the given size, evaluated in space.
+  Checkers {
+    odd: Arc<Texture>,
+    even: Arc<Texture>,
+    size: T,
+    space: TextureSpace,
+  },
+  Noise {
+    noise: Arc<Noise>,
+    space: TextureSpace,
+  },
+  // Another texture, looked up at the coordinates the mapping gives.
+  Mapped(Arc<Texture>, UvMapping),
+  // Blends from the first texture to the second by the factor, read as a
+  // scalar per channel, so a factor can be a color too.
+  Mix(Arc<Texture>, Arc<Texture>, Arc<Texture>),
+  Add(Arc<Texture>, Arc<Texture>),
+  Multiply(Arc<Texture>, Arc<Texture>),
+  // One minus each channel.
+  Invert(Arc<Texture>),
+  // The average of the channels, as a gray.
+  Grayscale(Arc<Texture>),
+  // Maps the input, read as a scalar, to a color by interpolating between
+  // stops, which are sorted by position.
+  Ramp(Arc<Texture>, Arc<Vec<(T, Color)>>),
 }
 
 impl Texture {
-  // u, v are surface coordinates.
-  pub fn value(&self, u: T, v: T, p: Point) -> Color {
+  pub fn value(&self, tc: &TexCoord) -> Color {
     match self {
       Texture::Color(x) => *x,
-      Texture::Checkers(ref white, ref black) => get_checkers_color(&**white, &**black, u, v, p),
-      Texture::Image(ref buf) => get_image_color(&**buf, u, v, p)
+      Texture::Checkers {
+        odd,
+        even,
+        size,
+        space,
+      } => {
+        let p = space.point(tc).0 / *size;
+        let cells = p.x().floor() + p.y().floor() + p.z().floor();
+        if cells.rem_euclid(2.0) == 1.0 {
+          odd.value(tc)
+        } else {
+          even.value(tc)
+        }
+      }
+      Texture::Image(ref image) => image.value(tc.u, tc.v, tc.du, tc.dv),
+      Texture::Noise { noise, space } => {
+        let x = noise.value(space.point(tc));
+        Color::new(x, x, x)
+      }
+      Texture::Mapped(texture, mapping) => {
+        mapping.evaluate(tc, |tc| texture.value(tc))
+      }
+      Texture::Mix(a, b, factor) => {
+        let t = factor.value(tc);
+        let one = Color::new(1.0, 1.0, 1.0);
+        (one - t) * a.value(tc) + t * b.value(tc)
+      }
+      Texture::Add(a, b) => a.value(tc) + b.value(tc),
+      Texture::Multiply(a, b) => a.value(tc) * b.value(tc),
+      Texture::Invert(a) => Color::new(1.0, 1.0, 1.0) - a.value(tc),
+      Texture::Grayscale(a) => {
+        let x = average(a.value(tc));
+        Color::new(x, x, x)
+      }
+      Texture::Ramp(input, stops) => ramp(stops, average(input.value(tc))),
     }
   }
 
-  pub fn from_image_filename(filename: &str) -> Texture {
-    Texture::Image(Arc::new(open(filename).unwrap().into_rgb8()))
+  // A uniform gray texture, for scalar parameters that don't vary.
+  pub fn constant(x: T) -> Texture {
+    Texture::Color(Color::new(x, x, x))
   }
-}
 
-fn get_checkers_color(white: &Texture, black: &Texture, u: T, v: T, p: Point) -> Color {
-  let w = 10.0 * p.0;
-  let sines = w.x().sin() * w.y().sin() * w.z().sin();
-  if sines < 0.0 {
-    white.value(u, v, p)
-  } else {
-    black.value(u, v, p)
+  // Evaluates the texture over the area a ray hit covers.
+  pub fn sample(&self, hit: &HitResultPayload) -> Color {
+    self.value(&TexCoord::from_hit(hit))
   }
-}
 
-fn clamp<T: PartialOrd>(x: T, lo: T, hi: T) -> T {
-  if x < lo {
-    return lo;
+  // Evaluates a texture that drives a scalar parameter, such as roughness, by
+  // averaging its channels.
+  pub fn scalar_sample(&self, hit: &HitResultPayload) -> T {
+    average(self.sample(hit))
   }
-  if x > hi {
-    return hi;
+
+  pub fn mix(a: Texture, b: Texture, factor: Texture) -> Texture {
+    Texture::Mix(Arc::new(a), Arc::new(b), Arc::new(factor))
+  }
+
+  // Maps this texture through a color ramp. Stops needn't be sorted.
+  pub fn ramp(self, stops: &[(T, Color)]) -> Texture {
+    let mut stops = stops.to_vec();
+    stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    Texture::Ramp(Arc::new(self), Arc::new(stops))
+  }
+
+  // A world space checkerboard, alternating between two textures.
+  pub fn new_checkers(odd: Texture, even: Texture, size: T) -> Texture {
+    Texture::Checkers {
+      odd: Arc::new(odd),
+      even: Arc::new(even),
+      size,
+      space: TextureSpace::World,
+    }
   }
-  return x;
-}
 
-fn get_image_color(image: &RgbImage, mut u: T, mut v: T, _p: Point) -> Color {
-  u = clamp(u, 0.0, 1.0);
-  v = 1.0 - clamp(v, 0.0, 1.0);
+  // A grayscale procedural noise texture, evaluated in world space. See
+  // Noise for what the parameters mean.
+  pub fn new_noise(
+    pattern: NoisePattern,
+    scale: T,
+    octaves: u32,
+    seed: u64,
+  ) -> Texture {
+    Texture::Noise {
+      noise: Arc::new(Noise {
+        perlin: Perlin::new(seed),
+        pattern,
+        scale,
+        octaves,
+      }),
+      space: TextureSpace::World,
+    }
+  }
 
-  let i = clamp((u * image.width() as f32) as u32, 0, image.width() - 1);
-  let j = clamp((v * image.height() as f32) as u32, 0, image.height() - 1);
+  // Evaluates a checkers or noise texture in the given space instead.
+  pub fn in_space(mut self, space: TextureSpace) -> Texture {
+    match self {
+      Texture::Checkers {
+        space: ref mut s, ..
+      }
+      | Texture::Noise {
+        space: ref mut s, ..
+      } => *s = space,
+      _ => panic!("Only procedural textures are evaluated in a space."),
+    }
+    self
+  }
 
-  let color_scale = 1.0 / 255.0;
-  let pixel:&Rgb<u8> = image.get_pixel(i, j);
+  // Looks this texture up at the coordinates mapping gives.
+  pub fn mapped(self, mapping: UvMapping) -> Texture {
+    Texture::Mapped(Arc::new(self), mapping)
+  }
+}
+
+fn average(c: Color) -> T {
+  (c.r() + c.g() + c.b()) / 3.0
+}
 
-  color_scale * Color::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32) 
+// Interpolates the sorted stops at x, holding the end colors beyond them.
+fn ramp(stops: &[(T, Color)], x: T) -> Color {
+  match stops.iter().position(|&(position, _)| position > x) {
+    None => stops.last().map_or(Color::new(0.0, 0.0, 0.0), |s| s.1),
+    Some(0) => stops[0].1,
+    Some(i) => {
+      let (x0, c0) = stops[i - 1];
+      let (x1, c1) = stops[i];
+      let t = (x - x0) / (x1 - x0);
+      (1.0 - t) * c0 + t * c1
+    }
+  }
 }
@@ -0,0 +1,388 @@
+// A small language for building textures out of nodes, so they can be
+// written in scene descriptions and on the command line instead of in Rust.
+//
+// An expression is a number (a uniform gray), or a node applied to
+// arguments:
+//
+//   rgb(r, g, b)                    A uniform color.
+//   image("path"[, srgb|linear])    An image, decoded as sRGB by default.
+//   checkers(odd, even, size[, uv|world|object])
+//   noise(plain|turbulence|marble|wood, scale[, octaves[, seed]][, space])
+//   mix(a, b, factor)
+//   add(a, b)
+//   multiply(a, b)
+//   invert(a)
+//   grayscale(a)
+//   ramp(input, position, color, position, color, ...)
+//
+// For example, mix(rgb(0.8, 0.1, 0.1), 0.3, ramp(noise(turbulence, 6),
+// 0.4, 0, 0.5, 1)) is red paint worn through to gray in patches.
+use crate::image_texture::*;
+use crate::mapping::*;
+use crate::noise::*;
+use crate::texture::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+type T = f32;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Number(T),
+  Word(String),
+  Str(String),
+  Open,
+  Close,
+  Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = Vec::new();
+  let mut chars = source.char_indices().peekable();
+  while let Some(&(start, c)) = chars.peek() {
+    match c {
+      c if c.is_whitespace() => {
+        chars.next();
+      }
+      '(' | ')' | ',' => {
+        chars.next();
+        tokens.push(match c {
+          '(' => Token::Open,
+          ')' => Token::Close,
+          _ => Token::Comma,
+        });
+      }
+      '"' => {
+        chars.next();
+        let mut s = String::new();
+        loop {
+          match chars.next() {
+            Some((_, '"')) => break,
+            Some((_, c)) => s.push(c),
+            None => return Err("Unterminated string".to_string()),
+          }
+        }
+        tokens.push(Token::Str(s));
+      }
+      c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+        let mut end = start;
+        while let Some(&(i, c)) = chars.peek() {
+          let exponent_sign = (c == '-' || c == '+')
+            && i > start
+            && source[..i].ends_with(['e', 'E']);
+          if c.is_ascii_digit()
+            || c == '.'
+            || c == 'e'
+            || c == 'E'
+            || exponent_sign
+            || i == start
+          {
+            end = i + c.len_utf8();
+            chars.next();
+          } else {
+            break;
+          }
+        }
+        let text = &source[start..end];
+        let x = text.parse().map_err(|_| format!("Bad number: {}", text))?;
+        tokens.push(Token::Number(x));
+      }
+      c if c.is_alphabetic() || c == '_' => {
+        let mut end = start;
+        while let Some(&(i, c)) = chars.peek() {
+          if c.is_alphanumeric() || c == '_' {
+            end = i + c.len_utf8();
+            chars.next();
+          } else {
+            break;
+          }
+        }
+        tokens.push(Token::Word(source[start..end].to_string()));
+      }
+      _ => return Err(format!("Unexpected character: {}", c)),
+    }
+  }
+  Ok(tokens)
+}
+
+// An argument to a node, before we know what the node wants it to be.
+enum Value {
+  Number(T),
+  Word(String),
+  Str(String),
+  Texture(Texture),
+}
+
+impl Value {
+  fn texture(self) -> Result<Texture, String> {
+    match self {
+      Value::Number(x) => Ok(Texture::constant(x)),
+      Value::Texture(t) => Ok(t),
+      Value::Word(w) => Err(format!("Expected a texture, found {}", w)),
+      Value::Str(s) => Err(format!("Expected a texture, found \"{}\"", s)),
+    }
+  }
+
+  fn number(self) -> Result<T, String> {
+    match self {
+      Value::Number(x) => Ok(x),
+      _ => Err("Expected a number".to_string()),
+    }
+  }
+
+  // A uniform color: a number or an rgb node.
+  fn color(self) -> Result<Color, String> {
+    match self.texture()? {
+      Texture::Color(c) => Ok(c),
+      _ => Err("Expected a number or an rgb color".to_string()),
+    }
+  }
+
+  fn word(self) -> Result<String, String> {
+    match self {
+      Value::Word(w) => Ok(w),
+      _ => Err("Expected a name".to_string()),
+    }
+  }
+}
+
+struct Parser<'a> {
+  tokens: Vec<Token>,
+  position: usize,
+  // Loads images, given a path and color space.
+  load_image: &'a mut dyn FnMut(&str, ColorSpace) -> Result<Texture, String>,
+}
+
+impl Parser<'_> {
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+    token
+  }
+
+  fn value(&mut self) -> Result<Value, String> {
+    match self.next() {
+      Some(Token::Number(x)) => Ok(Value::Number(x)),
+      Some(Token::Str(s)) => Ok(Value::Str(s)),
+      Some(Token::Word(name)) => {
+        if self.tokens.get(self.position) != Some(&Token::Open) {
+          return Ok(Value::Word(name));
+        }
+        self.position += 1;
+        let mut args = Vec::new();
+        if self.tokens.get(self.position) == Some(&Token::Close) {
+          self.position += 1;
+        } else {
+          loop {
+            args.push(self.value()?);
+            match self.next() {
+              Some(Token::Comma) => continue,
+              Some(Token::Close) => break,
+              _ => return Err(format!("Expected , or ) in {}", name)),
+            }
+          }
+        }
+        self
+          .node(&name, args)
+          .map(Value::Texture)
+          .map_err(|e| format!("In {}: {}", name, e))
+      }
+      Some(token) => Err(format!("Unexpected {:?}", token)),
+      None => Err("Unexpected end".to_string()),
+    }
+  }
+
+  fn node(&mut self, name: &str, args: Vec<Value>) -> Result<Texture, String> {
+    let count = args.len();
+    let arity = |lo: usize, hi: usize| {
+      if count < lo || count > hi {
+        Err(format!("Wrong number of arguments: {}", count))
+      } else {
+        Ok(())
+      }
+    };
+    let mut args = args.into_iter();
+    let mut arg = || args.next().unwrap();
+    let texture = match name {
+      "rgb" => {
+        arity(3, 3)?;
+        let (r, g, b) = (arg().number()?, arg().number()?, arg().number()?);
+        Texture::Color(Color::new(r, g, b))
+      }
+      "image" => {
+        arity(1, 2)?;
+        let path = match arg() {
+          Value::Str(s) => s,
+          _ => return Err("Expected a path in quotes".to_string()),
+        };
+        let color_space = if count == 2 {
+          match arg().word()?.as_str() {
+            "srgb" => ColorSpace::Srgb,
+            "linear" => ColorSpace::Linear,
+            other => return Err(format!("Unknown color space: {}", other)),
+          }
+        } else {
+          ColorSpace::Srgb
+        };
+        (self.load_image)(&path, color_space)?
+      }
+      "checkers" => {
+        arity(3, 4)?;
+        let checkers = Texture::new_checkers(
+          arg().texture()?,
+          arg().texture()?,
+          arg().number()?,
+        );
+        if count == 4 {
+          checkers.in_space(space(&arg().word()?)?)
+        } else {
+          checkers
+        }
+      }
+      "noise" => {
+        arity(2, 5)?;
+        let pattern = match arg().word()?.as_str() {
+          "plain" => NoisePattern::Plain,
+          "turbulence" => NoisePattern::Turbulence,
+          "marble" => NoisePattern::Marble,
+          "wood" => NoisePattern::Wood,
+          other => return Err(format!("Unknown noise pattern: {}", other)),
+        };
+        let scale = arg().number()?;
+        let (mut octaves, mut seed, mut texture_space) =
+          (7, 0, TextureSpace::World);
+        for i in 2..count {
+          match arg() {
+            Value::Number(x) if i == 2 => octaves = x as u32,
+            Value::Number(x) if i == 3 => seed = x as u64,
+            Value::Word(w) if i == count - 1 => texture_space = space(&w)?,
+            _ => return Err("Expected octaves, a seed or a space".to_string()),
+          }
+        }
+        Texture::new_noise(pattern, scale, octaves, seed)
+          .in_space(texture_space)
+      }
+      "mix" => {
+        arity(3, 3)?;
+        Texture::mix(arg().texture()?, arg().texture()?, arg().texture()?)
+      }
+      "add" => {
+        arity(2, 2)?;
+        Texture::Add(Arc::new(arg().texture()?), Arc::new(arg().texture()?))
+      }
+      "multiply" => {
+        arity(2, 2)?;
+        Texture::Multiply(
+          Arc::new(arg().texture()?),
+          Arc::new(arg().texture()?),
+        )
+      }
+      "invert" => {
+        arity(1, 1)?;
+        Texture::Invert(Arc::new(arg().texture()?))
+      }
+      "grayscale" => {
+        arity(1, 1)?;
+        Texture::Grayscale(Arc::new(arg().texture()?))
+      }
+      "ramp" => {
+        if count < 3 || count % 2 == 0 {
+          return Err(
+            "Expected an input and position, color pairs".to_string(),
+          );
+        }
+        let input = arg().texture()?;
+        let mut stops = Vec::new();
+        for _ in 0..count / 2 {
+          stops.push((arg().number()?, arg().color()?));
+        }
+        input.ramp(&stops)
+      }
+      _ => return Err("Unknown node".to_string()),
+    };
+    Ok(texture)
+  }
+}
+
+fn space(name: &str) -> Result<TextureSpace, String> {
+  match name {
+    "uv" => Ok(TextureSpace::Uv),
+    "world" => Ok(TextureSpace::World),
+    "object" => Ok(TextureSpace::Object),
+    other => Err(format!("Unknown texture space: {}", other)),
+  }
+}
+
+// Parses a texture expression, loading its images with load_image.
+pub fn parse_texture(
+  source: &str,
+  load_image: &mut dyn FnMut(&str, ColorSpace) -> Result<Texture, String>,
+) -> Result<Texture, String> {
+  let mut parser = Parser {
+    tokens: tokenize(source)?,
+    position: 0,
+    load_image,
+  };
+  let texture = parser.value()?.texture()?;
+  if parser.position < parser.tokens.len() {
+    return Err(format!(
+      "Unexpected {:?} after the texture",
+      parser.tokens[parser.position]
+    ));
+  }
+  Ok(texture)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(source: &str) -> Result<Texture, String> {
+    parse_texture(source, &mut |_, _| Err("No images here".to_string()))
+  }
+
+  fn at(texture: &Texture, x: T) -> Color {
+    texture.value(&TexCoord {
+      u: x,
+      v: 0.0,
+      du: 0.0,
+      dv: 0.0,
+      p: Point::new(x, 0.0, 0.0),
+      object_p: Point::new(x, 0.0, 0.0),
+      normal: Vec3::new(0.0, 1.0, 0.0),
+      footprint: 0.0,
+    })
+  }
+
+  #[test]
+  fn test_nodes() {
+    let texture =
+      parse("add(multiply(rgb(1, 0.5, 0), 0.5), invert(grayscale(0.75)))")
+        .unwrap();
+    let c = at(&texture, 0.0);
+    assert_eq!((c.r(), c.g(), c.b()), (0.75, 0.5, 0.25));
+
+    let mix = parse("mix(0, rgb(1, 1, 1), rgb(0.25, 0.5, 1))").unwrap();
+    let c = at(&mix, 0.0);
+    assert_eq!((c.r(), c.g(), c.b()), (0.25, 0.5, 1.0));
+  }
+
+  #[test]
+  fn test_ramp() {
+    // The input is the checkers' cell, which is 1 from x = 1 to 2.
+    let ramp = parse("ramp(checkers(1, 0, 1), 0, 0, 1, rgb(1, 0, 0))").unwrap();
+    assert_eq!(at(&ramp, 0.5).r(), 0.0);
+    assert_eq!(at(&ramp, 1.5).r(), 1.0);
+    let gradient = parse("ramp(0.25, 0, 0, 0.5, 1, -1, 7)").unwrap();
+    assert_eq!(at(&gradient, 0.0).g(), 0.5);
+  }
+
+  #[test]
+  fn test_errors() {
+    assert!(parse("mix(1, 2)").is_err());
+    assert!(parse("rgb(1, 2, 3").is_err());
+    assert!(parse("noise(swirly, 4)").is_err());
+    assert!(parse("blur(1)").is_err());
+    assert!(parse("1 2").is_err());
+  }
+}
@@ -0,0 +1,107 @@
+// Thin-film interference, for soap bubbles, oil slicks and coated lenses.
+//
+// Light reflecting off the top and the bottom of a film about as thick as its
+// wavelength interferes with itself, so the reflectance of the coated surface
+// oscillates with wavelength and angle. That is what makes these surfaces
+// iridescent.
+use crate::vec3::*;
+use std::f32::consts::PI;
+
+type T = f32;
+
+// The wavelengths, in nanometers, at which RGB renders evaluate each channel.
+pub const RGB_WAVELENGTHS: [T; 3] = [630.0, 532.0, 465.0];
+
+#[derive(Clone, Copy, Debug)]
+pub struct ThinFilm {
+  // In nanometers.
+  pub thickness: T,
+  pub refraction_index: T,
+}
+
+// The s and p amplitude reflection coefficients at an interface from a medium
+// of index n1 into one of index n2, given the cosines on each side.
+fn amplitude_reflection(n1: T, n2: T, cos1: T, cos2: T) -> (T, T) {
+  let s = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+  let p = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+  (s, p)
+}
+
+impl ThinFilm {
+  // The reflectance of a surface coated with this film, for light coming from
+  // a medium of index n1 at an angle with cosine cos_theta_i, onto a base of
+  // index n3.
+  //
+  // This sums the infinite series of reflections inside the film (the Airy
+  // formula) for each polarization. All indices are real, so the phase
+  // changes on reflection are just the signs of the coefficients.
+  pub fn reflectance(&self, cos_theta_i: T, n1: T, n3: T, wavelength: T) -> T {
+    let n2 = self.refraction_index;
+    let cos1 = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_1 = 1.0 - cos1 * cos1;
+    let sin2_2 = sin2_1 * (n1 / n2).powi(2);
+    let sin2_3 = sin2_1 * (n1 / n3).powi(2);
+    if sin2_2 >= 1.0 || sin2_3 >= 1.0 {
+      // No light makes it through, and none is absorbed.
+      return 1.0;
+    }
+    let cos2 = (1.0 - sin2_2).sqrt();
+    let cos3 = (1.0 - sin2_3).sqrt();
+
+    let (r12_s, r12_p) = amplitude_reflection(n1, n2, cos1, cos2);
+    let (r23_s, r23_p) = amplitude_reflection(n2, n3, cos2, cos3);
+    // The phase difference between consecutive reflections.
+    let cos_delta = (4.0 * PI * n2 * self.thickness * cos2 / wavelength).cos();
+    let airy = |r12: T, r23: T| -> T {
+      let cross = 2.0 * r12 * r23 * cos_delta;
+      (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+    };
+    0.5 * (airy(r12_s, r23_s) + airy(r12_p, r23_p))
+  }
+
+  // The reflectance at each RGB_WAVELENGTHS, over a base whose index can
+  // differ per channel.
+  pub fn rgb_reflectance(&self, cos_theta_i: T, n1: T, n3: [T; 3]) -> Color {
+    let r =
+      |i: usize| self.reflectance(cos_theta_i, n1, n3[i], RGB_WAVELENGTHS[i]);
+    Color::new(r(0), r(1), r(2))
+  }
+}
+
+// The real index of refraction with the given reflectance at normal
+// incidence, in air. Coated conductors use this to stand in for their complex
+// index, as in Belcour and Barla, "A Practical Extension to Microfacet Theory
+// for the Modeling of Varying Iridescence" (2017).
+pub fn refraction_index_from_reflectance(f0: T) -> T {
+  let sqrt_f0 = f0.clamp(0.0, 0.99).sqrt();
+  (1.0 + sqrt_f0) / (1.0 - sqrt_f0)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::microfacet::fresnel_dielectric;
+
+  #[test]
+  fn test_vanishing_film_matches_fresnel() {
+    let film = ThinFilm {
+      thickness: 0.0,
+      refraction_index: 1.33,
+    };
+    for &cos_theta in &[1.0, 0.8, 0.5, 0.2] {
+      let r = film.reflectance(cos_theta, 1.0, 1.5, 550.0);
+      assert!((r - fresnel_dielectric(cos_theta, 1.5)).abs() < 1e-4);
+    }
+  }
+
+  #[test]
+  fn test_quarter_wave_coating_cancels_reflection() {
+    // An anti-reflective coating: a quarter-wave film with index sqrt(1.5).
+    let n2 = (1.5 as T).sqrt();
+    let film = ThinFilm {
+      thickness: 550.0 / (4.0 * n2),
+      refraction_index: n2,
+    };
+    assert!(film.reflectance(1.0, 1.0, 1.5, 550.0) < 1e-5);
+  }
+}
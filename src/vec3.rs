@@ -1,7 +1,7 @@
 use std::f32::consts::PI;
 use rand::Rng;
 
-use std::ops::{Add, AddAssign, DivAssign, Div, Mul, MulAssign, Sub, SubAssign, Neg};
+use std::ops::{Add, AddAssign, DivAssign, Div, Mul, Sub, Neg};
 
 #[cfg(target_arch = "x86_64")]
 use core::arch::x86_64::*;
@@ -47,7 +47,7 @@ impl Vec3 {
     )
   }
   pub fn near_zero(self) -> bool {
-    return self.abs().max_element() <= 1e-8;
+    self.abs().max_element() <= 1e-8
   }
   pub fn normalize(self) -> Self {
     unsafe {
@@ -284,6 +284,10 @@ impl Color {
   pub fn b(self) -> T {
     self.0.z()
   }
+  // The brightness of linear sRGB, as Rec. 709 weighs it.
+  pub fn luminance(self) -> T {
+    0.2126 * self.r() + 0.7152 * self.g() + 0.0722 * self.b()
+  }
 }
 
 impl Mul<T> for Color {
@@ -314,6 +318,13 @@ impl Add<Color> for Color {
   }
 }
 
+impl Sub<Color> for Color {
+  type Output = Color;
+  fn sub(self, rhs: Color) -> Color {
+    Color(self.0 - rhs.0)
+  }
+}
+
 impl AddAssign<Color> for Color {
   fn add_assign(&mut self, rhs: Color) {
     self.0 += rhs.0;
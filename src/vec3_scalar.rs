@@ -46,7 +46,7 @@ impl Vec3 {
         return false;
       }
     }
-    return true;
+    true
   }
 
   pub fn norm_squared(&self) -> T {
@@ -242,7 +242,7 @@ impl DivAssign<T> for Vec3 {
 pub struct Point(pub Vec3);
 impl Point {
   pub fn new(x: T, y: T, z: T) -> Point {
-    Point(Vec3 { x: x, y: y, z: z })
+    Point(Vec3 { x, y, z })
   }
 }
 
@@ -321,7 +321,7 @@ impl IndexMut<usize> for Point {
 pub struct Color(pub Vec3);
 impl Color {
   pub const fn new(x: T, y: T, z: T) -> Color {
-    Color(Vec3 { x: x, y: y, z: z })
+    Color(Vec3 { x, y, z })
   }
 
   pub fn random() -> Color {